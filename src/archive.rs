@@ -0,0 +1,125 @@
+use std::{
+    fs::{self, File},
+    io::{self, prelude::*, BufReader},
+};
+
+use anyhow::{Context, Result};
+use bytesize::ByteSize;
+use camino::{Utf8Path, Utf8PathBuf};
+use flate2::read::GzDecoder;
+use log::*;
+
+use crate::{
+    compress_to, metrics::Stats, report_failures, temp_acmi, template::OutputTemplate, Args,
+};
+
+/// Compress every ACMI (`.acmi` or `.zip.acmi`) in a tar archive, gzipped or not.
+///
+/// Outputs are named by expanding `template` with each member's path in the archive,
+/// so `{dir}` mirrors the archive's directories.
+/// Compressing needs to seek, so each member is spooled to a temporary file
+/// (one at a time, not the whole archive) and compressed from there.
+pub fn compress(
+    tar: &Utf8Path,
+    template: &OutputTemplate,
+    index_template: Option<&OutputTemplate>,
+    args: &Args,
+) -> Result<Stats> {
+    let fh = BufReader::new(File::open(tar).with_context(|| format!("Couldn't open {tar}"))?);
+    let mut archive = tar::Archive::new(decompress(fh)?);
+    let spool = temp_acmi("archive")?;
+
+    let mut stats = Stats::default();
+    let mut members = 0;
+    let mut failures = vec![];
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Couldn't read {tar}"))?;
+    for entry in entries {
+        let mut entry = entry.with_context(|| format!("Couldn't read {tar}"))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let member = match Utf8PathBuf::try_from(entry.path()?.into_owned()) {
+            Ok(m) if m.as_str().ends_with(".acmi") => m,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Skipping a member of {tar}: {e}");
+                continue;
+            }
+        };
+        members += 1;
+
+        let result = spool_to(&mut entry, &spool)
+            .and_then(|len| compress_member(&member, &spool, len, template, index_template, args));
+        match result {
+            Ok(s) => stats += s,
+            Err(e) if args.fail_fast => {
+                remove_spool(&spool);
+                return Err(e);
+            }
+            Err(e) => {
+                error!("{e:#}");
+                failures.push((member, e));
+            }
+        }
+    }
+    remove_spool(&spool);
+
+    info!(
+        "Compressed {} of {members} ACMIs in {tar}",
+        members - failures.len()
+    );
+    report_failures(&failures, members)?;
+    Ok(stats)
+}
+
+/// Gunzip the archive if it's gzipped (by its magic bytes, like ZIPs).
+fn decompress<'a, R: BufRead + 'a>(mut fh: R) -> Result<Box<dyn Read + 'a>> {
+    if fh.fill_buf()?.starts_with(b"\x1f\x8b") {
+        Ok(Box::new(GzDecoder::new(fh)))
+    } else {
+        Ok(Box::new(fh))
+    }
+}
+
+/// Copy the member out of the archive, returning its length.
+fn spool_to(member: &mut impl Read, spool: &Utf8Path) -> Result<u64> {
+    let mut fh = File::create(spool).with_context(|| format!("Couldn't create {spool}"))?;
+    let len = io::copy(member, &mut fh).with_context(|| format!("Couldn't write {spool}"))?;
+    fh.flush()?;
+    Ok(len)
+}
+
+fn compress_member(
+    member: &Utf8Path,
+    spool: &Utf8Path,
+    len: u64,
+    template: &OutputTemplate,
+    index_template: Option<&OutputTemplate>,
+    args: &Args,
+) -> Result<Stats> {
+    if len == 0 {
+        warn!("Skipping {member}: it's empty");
+        return Ok(Stats::default());
+    }
+    let output = template.expand(member);
+    let index = index_template.map(|t| t.expand(member));
+    let stats = compress_to(spool, &output, index, args)
+        .with_context(|| format!("Couldn't compress {member}"))?;
+    println!(
+        "{member}: {} -> {} ({:.1}%)",
+        ByteSize::b(stats.original_bytes),
+        ByteSize::b(stats.compressed_bytes),
+        stats.compressed_bytes as f64 / stats.original_bytes as f64 * 100.0
+    );
+    Ok(stats)
+}
+
+fn remove_spool(spool: &Utf8Path) {
+    if let Err(e) = fs::remove_file(spool) {
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!("Couldn't remove {spool}: {e}");
+        }
+    }
+}
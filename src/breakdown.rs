@@ -0,0 +1,139 @@
+use std::{cell::Cell, io, rc::Rc};
+
+use anyhow::Result;
+use bytesize::ByteSize;
+use log::*;
+use tacview::record::Record;
+
+use crate::CountingWriter;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Category {
+    Global,
+    Frame,
+    Update,
+    Remove,
+    Event,
+}
+
+const CATEGORIES: [Category; 5] = [
+    Category::Global,
+    Category::Frame,
+    Category::Update,
+    Category::Remove,
+    Category::Event,
+];
+
+impl Category {
+    pub fn of(rec: &Record) -> Self {
+        match rec {
+            Record::GlobalProperty(_) => Self::Global,
+            Record::Frame(_) => Self::Frame,
+            Record::Update(_) => Self::Update,
+            Record::Remove(_) => Self::Remove,
+            Record::Event(_) => Self::Event,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Global => "global",
+            Self::Frame => "frame",
+            Self::Update => "update",
+            Self::Remove => "remove",
+            Self::Event => "event",
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Tally {
+    original: u64,
+    compressed: u64,
+}
+
+/// Measures how many bytes each kind of record took up as it came in,
+/// before the pipeline (and any lossy options like --drop-static) got to it.
+///
+/// We don't have the original bytes of each record, just what the parser gave us,
+/// so original sizes are measured by writing each record back out as Tacview text.
+/// They won't add up to the input's size exactly (number formatting, the header...),
+/// but they're close enough to see where the savings come from.
+pub struct Originals {
+    sizes: [u64; CATEGORIES.len()],
+    measure: tacview::Writer<CountingWriter<io::Sink>>,
+    measured: Rc<Cell<u64>>,
+}
+
+impl Originals {
+    pub fn new() -> Result<Self> {
+        let sink = CountingWriter::new(io::sink());
+        let measured = sink.counter();
+        Ok(Self {
+            sizes: Default::default(),
+            measure: tacview::Writer::new(sink)?,
+            measured,
+        })
+    }
+
+    /// Count the size of a record as it came in.
+    pub fn measure(&mut self, rec: &Record) -> Result<()> {
+        let before = self.measured.get();
+        self.measure.write(rec.clone())?;
+        self.sizes[Category::of(rec) as usize] += self.measured.get() - before;
+        Ok(())
+    }
+
+    /// What we measured, to hand to a [`SizeBreakdown`]
+    pub fn sizes(&self) -> [u64; CATEGORIES.len()] {
+        self.sizes
+    }
+}
+
+/// Tallies how many bytes each kind of record takes up before and after compression.
+#[derive(Debug, Default)]
+pub struct SizeBreakdown {
+    tallies: [Tally; CATEGORIES.len()],
+}
+
+impl SizeBreakdown {
+    /// Fill in the original sizes, as measured by [`Originals`].
+    pub fn originals(&mut self, sizes: [u64; CATEGORIES.len()]) {
+        for (t, size) in self.tallies.iter_mut().zip(sizes) {
+            t.original = size;
+        }
+    }
+
+    /// Count the bytes we wrote for a record.
+    pub fn compressed(&mut self, category: Category, written: u64) {
+        self.tallies[category as usize].compressed += written;
+    }
+
+    /// The fraction of the category's original bytes that compression removed
+    pub fn savings(&self, category: Category) -> f64 {
+        let t = &self.tallies[category as usize];
+        if t.original == 0 {
+            0.0
+        } else {
+            1.0 - t.compressed as f64 / t.original as f64
+        }
+    }
+
+    pub fn log(&self) {
+        info!("Size breakdown by record type:");
+        for c in CATEGORIES {
+            let t = &self.tallies[c as usize];
+            let ratio = if t.original == 0 {
+                0.0
+            } else {
+                t.compressed as f64 / t.original as f64 * 100.0
+            };
+            info!(
+                "{:>8}: {} -> {} ({ratio:.1}%)",
+                c.name(),
+                ByteSize::b(t.original),
+                ByteSize::b(t.compressed),
+            );
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use rustc_hash::FxHashMap;
+use tacview::record::{Coords, Property, Update};
+
+/// The update's coordinates, if it has any
+pub fn of(up: &Update) -> Option<&Coords> {
+    up.props.iter().find_map(|p| match p {
+        Property::T(c) => Some(c),
+        _ => None,
+    })
+}
+
+/// Fill in the coordinates from the ones that changed.
+/// (Like all properties, coordinates that didn't change are left blank.)
+pub fn merge(into: &mut Coords, from: &Coords) {
+    for (i, f) in [
+        (&mut into.longitude, from.longitude),
+        (&mut into.latitude, from.latitude),
+        (&mut into.altitude, from.altitude),
+        (&mut into.u, from.u),
+        (&mut into.v, from.v),
+        (&mut into.roll, from.roll),
+        (&mut into.pitch, from.pitch),
+        (&mut into.yaw, from.yaw),
+        (&mut into.heading, from.heading),
+    ] {
+        *i = f.or(*i);
+    }
+}
+
+/// Everything we know about where each object is,
+/// put back together from the coordinates each update changed.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    objects: FxHashMap<u64, Coords>,
+}
+
+impl Tracker {
+    /// Merge in the update's coordinates, returning where the object is now
+    /// (or None if the update didn't have any).
+    pub fn update(&mut self, up: &Update) -> Option<&Coords> {
+        let c = of(up)?;
+        let current = self.objects.entry(up.id).or_default();
+        merge(current, c);
+        Some(current)
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.objects.remove(&id);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Coords)> {
+        self.objects.iter().map(|(id, c)| (*id, c))
+    }
+}
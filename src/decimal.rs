@@ -0,0 +1,213 @@
+use std::io::{self, prelude::*};
+
+use log::*;
+
+/// Rewrites comma decimal separators (`T=12,5|42,1|1000`) to dots
+/// so that files exported with a European locale can be parsed.
+///
+/// Commas also separate fields in object updates, so we only rewrite a comma
+/// if it sits between two numbers and what follows it isn't a `Key=Value` field.
+/// Text with real commas in it (names, messages...) is escaped (`\,`) and left alone.
+pub struct DecimalCommas<R> {
+    inner: R,
+    line: Vec<u8>,
+    pos: usize,
+    line_number: u64,
+    rewritten: u64,
+}
+
+impl<R: BufRead> DecimalCommas<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            line: vec![],
+            pos: 0,
+            line_number: 0,
+            rewritten: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for DecimalCommas<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.inner.read_until(b'\n', &mut self.line)? == 0 {
+                if self.rewritten > 0 {
+                    debug!("Rewrote decimal commas on {} lines", self.rewritten);
+                }
+                return Ok(0);
+            }
+            self.line_number += 1;
+
+            if normalize(&mut self.line) {
+                if self.rewritten == 0 {
+                    info!(
+                        "Found decimal commas on line {}, rewriting them to dots",
+                        self.line_number
+                    );
+                }
+                self.rewritten += 1;
+            }
+        }
+
+        let n = (&self.line[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Rewrite decimal commas in the line, returning true if there were any.
+fn normalize(line: &mut [u8]) -> bool {
+    if line.starts_with(b"//") {
+        return false;
+    }
+
+    // Frame lines (#12,5) only hold a number.
+    if line.first() == Some(&b'#') {
+        let mut changed = false;
+        for b in line.iter_mut().filter(|b| **b == b',') {
+            *b = b'.';
+            changed = true;
+        }
+        return changed;
+    }
+
+    let mut changed = false;
+    // Where the value of the current field starts, if it has Key=
+    let mut value_start = None;
+
+    for i in 0..line.len() {
+        match line[i] {
+            b'=' if value_start.is_none() => value_start = Some(i + 1),
+            b',' if i == 0 || line[i - 1] != b'\\' => {
+                let value = match value_start {
+                    Some(v) => &line[v..i],
+                    None => &[],
+                };
+                let next = next_field(&line[i + 1..]);
+
+                if ends_numeric(value) && starts_numeric(next) {
+                    // Part of the same number; keep going in this field.
+                    line[i] = b'.';
+                    changed = true;
+                } else {
+                    value_start = None;
+                }
+            }
+            _ => {}
+        }
+    }
+    changed
+}
+
+/// The next comma-separated field, without line endings
+fn next_field(rest: &[u8]) -> &[u8] {
+    let mut end = rest.len();
+    for (i, b) in rest.iter().enumerate() {
+        let escaped = i > 0 && rest[i - 1] == b'\\';
+        if (*b == b',' && !escaped) || *b == b'\r' || *b == b'\n' {
+            end = i;
+            break;
+        }
+    }
+    &rest[..end]
+}
+
+fn is_numeric(b: &u8) -> bool {
+    b.is_ascii_digit() || matches!(b, b'.' | b'-' | b'+' | b'|')
+}
+
+/// Is this a field value of numbers, ending in a digit?
+fn ends_numeric(value: &[u8]) -> bool {
+    value.last().is_some_and(u8::is_ascii_digit) && value.iter().all(is_numeric)
+}
+
+/// Is this the rest of a number (and not a Key=Value field)?
+fn starts_numeric(field: &[u8]) -> bool {
+    field.first().is_some_and(u8::is_ascii_digit) && field.iter().all(is_numeric)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Normalize the line, checking it says so if (and only if) it changed.
+    fn normalized(line: &str) -> String {
+        let mut bytes = line.as_bytes().to_vec();
+        let changed = normalize(&mut bytes);
+        let out = String::from_utf8(bytes).unwrap();
+        assert_eq!(changed, out != line, "{line:?} -> {out:?}");
+        out
+    }
+
+    #[test]
+    fn coordinates() {
+        assert_eq!(
+            normalized("a01,T=12,5|42,1|1000\n"),
+            "a01,T=12.5|42.1|1000\n"
+        );
+        assert_eq!(
+            normalized("a01,T=-0,5|+1,25|-3\r\n"),
+            "a01,T=-0.5|+1.25|-3\r\n"
+        );
+    }
+
+    #[test]
+    fn field_separators() {
+        assert_eq!(
+            normalized("a01,T=1,5|2,5|100,Name=F-16C,Heading=90,5\n"),
+            "a01,T=1.5|2.5|100,Name=F-16C,Heading=90.5\n"
+        );
+        // All-digit IDs aren't part of a number.
+        assert_eq!(normalized("101,IAS=250,5\n"), "101,IAS=250.5\n");
+        assert_eq!(
+            normalized("a01,T=1|2|3,Name=Viper\n"),
+            "a01,T=1|2|3,Name=Viper\n"
+        );
+        assert_eq!(
+            normalized("0,ReferenceLongitude=-116,5\n"),
+            "0,ReferenceLongitude=-116.5\n"
+        );
+    }
+
+    #[test]
+    fn escaped_commas() {
+        assert_eq!(
+            normalized("a01,Name=Viper\\,1,T=1,5|2|3\n"),
+            "a01,Name=Viper\\,1,T=1.5|2|3\n"
+        );
+        assert_eq!(normalized("a01,Name=12\\,5\n"), "a01,Name=12\\,5\n");
+        assert_eq!(
+            normalized("0,Event=Message|a01|Fox 2\\, Fox 2\n"),
+            "0,Event=Message|a01|Fox 2\\, Fox 2\n"
+        );
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(normalized("#12,5\n"), "#12.5\n");
+        assert_eq!(normalized("#12\n"), "#12\n");
+    }
+
+    #[test]
+    fn header() {
+        assert_eq!(normalized("FileVersion=2,1\n"), "FileVersion=2.1\n");
+        assert_eq!(
+            normalized("FileType=text/acmi/tacview\n"),
+            "FileType=text/acmi/tacview\n"
+        );
+        assert_eq!(normalized("// 1,5 comment\n"), "// 1,5 comment\n");
+    }
+
+    #[test]
+    fn streams_lines() {
+        let acmi = "FileVersion=2,1\n#0,5\na01,T=1,5|2|3\n";
+        let mut out = String::new();
+        DecimalCommas::new(acmi.as_bytes())
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "FileVersion=2.1\n#0.5\na01,T=1.5|2|3\n");
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use log::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use tacview::record::{EventKind, Record};
+
+use crate::transform::RecordTransform;
+
+/// Decides which records the read thread passes on to the writer.
+///
+/// Frames and the header's global properties are always kept;
+/// filters pick which events and objects' updates and removals survive.
+///
+/// Tacview writes mid-recording global properties and events as object 0,
+/// the "global object". It isn't an object as far as --only is concerned:
+/// it's kept unless dropped with --drop-global, and --keep-global keeps only it.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// If set, only keep these objects.
+    only: Option<FxHashSet<u64>>,
+    events: Events,
+    global: Global,
+    /// Have we seen a frame yet? Global properties before the first are the header.
+    past_header: bool,
+}
+
+/// What to do with the global object (ID 0)
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+enum Global {
+    #[default]
+    Keep,
+    /// Drop its global properties (after the header) and events
+    Drop,
+    /// Keep it and nothing else
+    Only,
+}
+
+#[derive(Debug, Default)]
+enum Events {
+    #[default]
+    All,
+    None,
+    /// Only keep the events with these keywords (lowercased)
+    Only(FxHashSet<String>),
+}
+
+/// Event keywords from the Tacview docs
+const EVENT_KEYWORDS: &[&str] = &[
+    "Message",
+    "Bookmark",
+    "Debug",
+    "LeftArea",
+    "Destroyed",
+    "TakenOff",
+    "Landed",
+    "Timeout",
+];
+
+impl Filter {
+    /// Keep only the given objects.
+    ///
+    /// If we have the parent of each child object (from their `Parent` properties),
+    /// also keep the children (weapons, sensors...) and parents of the given objects
+    /// so that replays aren't missing launchers or the missiles they launched.
+    pub fn only(mut self, ids: &[u64], parents: Option<&FxHashMap<u64, u64>>) -> Self {
+        let mut keep: FxHashSet<u64> = ids.iter().copied().collect();
+
+        if let Some(parents) = parents {
+            let mut children: FxHashMap<u64, Vec<u64>> = FxHashMap::default();
+            for (child, parent) in parents {
+                children.entry(*parent).or_default().push(*child);
+            }
+
+            let mut family = vec![];
+            for id in ids {
+                // Parents, grandparents...
+                let mut current = *id;
+                while let Some(parent) = parents.get(&current) {
+                    if !keep.insert(*parent) {
+                        break;
+                    }
+                    current = *parent;
+                }
+
+                // Children, grandchildren...
+                family.push(*id);
+                while let Some(f) = family.pop() {
+                    for child in children.get(&f).into_iter().flatten() {
+                        if keep.insert(*child) {
+                            family.push(*child);
+                        }
+                    }
+                }
+            }
+        }
+
+        debug!("Keeping {} objects", keep.len());
+        self.only = Some(keep);
+        self
+    }
+
+    /// Drop the global object's properties (after the header) and events.
+    pub fn drop_global(mut self) -> Self {
+        self.global = Global::Drop;
+        self
+    }
+
+    /// Keep only the global object, dropping every other object.
+    pub fn only_global(mut self) -> Self {
+        self.global = Global::Only;
+        self
+    }
+
+    /// Drop all events.
+    pub fn drop_events(mut self) -> Self {
+        self.events = Events::None;
+        self
+    }
+
+    /// Only keep events of the given types (`Destroyed`, `Message`, etc.)
+    pub fn only_events(mut self, kinds: &[String]) -> Self {
+        for k in kinds {
+            if !EVENT_KEYWORDS.iter().any(|e| e.eq_ignore_ascii_case(k)) {
+                warn!(
+                    "{k} isn't a standard Tacview event (expected one of {}); \
+                     filtering for it anyways",
+                    EVENT_KEYWORDS.join(", ")
+                );
+            }
+        }
+        self.events = Events::Only(kinds.iter().map(|k| k.to_lowercase()).collect());
+        self
+    }
+
+    pub fn keep(&mut self, rec: &Record) -> bool {
+        let id = match rec {
+            Record::Update(up) => up.id,
+            Record::Remove(id) => *id,
+            Record::Event(e) => {
+                if self.global == Global::Drop {
+                    return false;
+                }
+                return match &self.events {
+                    Events::All => true,
+                    Events::None => false,
+                    Events::Only(kinds) => kinds.contains(&event_keyword(&e.kind).to_lowercase()),
+                };
+            }
+            Record::GlobalProperty(_) => return !self.past_header || self.global != Global::Drop,
+            Record::Frame(_) => {
+                self.past_header = true;
+                return true;
+            }
+        };
+
+        if self.global == Global::Only {
+            return false;
+        }
+        match &self.only {
+            Some(only) => only.contains(&id),
+            None => true,
+        }
+    }
+}
+
+impl RecordTransform for Filter {
+    fn transform(&mut self, rec: Record, out: &mut Vec<Record>) {
+        if self.keep(&rec) {
+            out.push(rec);
+        }
+    }
+}
+
+/// Pick `n` of the given objects, pseudo-randomly but the same way every time
+/// for the same seed.
+pub fn sample(ids: &FxHashSet<u64>, n: usize, seed: u64) -> Vec<u64> {
+    let mut ids: Vec<u64> = ids.iter().copied().collect();
+    // Sort by a hash of each ID (and the seed), and take the first n.
+    // Unlike shuffling, an object's pick doesn't depend on which others are there.
+    let salt = mix(seed);
+    ids.sort_unstable_by_key(|id| (mix(id ^ salt), *id));
+    ids.truncate(n);
+    debug!("Sampled {} objects with seed {seed}", ids.len());
+    ids
+}
+
+/// A seed for when we weren't given one, from the time and our PID
+pub fn roll_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    mix(nanos ^ (u64::from(process::id()) << 32))
+}
+
+/// SplitMix64's finalizer; scatters similar IDs far apart.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// How object IDs are written on the command line and in our output.
+/// Tacview uses hex.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ArgEnum)]
+pub enum IdBase {
+    Hex,
+    Dec,
+}
+
+impl IdBase {
+    pub fn parse(self, id: &str) -> Result<u64> {
+        match self {
+            Self::Hex => u64::from_str_radix(id, 16)
+                .with_context(|| format!("{id:?} isn't a (hex) object ID")),
+            Self::Dec => match id.parse() {
+                Ok(id) => Ok(id),
+                Err(_) if u64::from_str_radix(id, 16).is_ok() => {
+                    bail!("{id:?} isn't a decimal object ID (it looks like hex; see --id-base)")
+                }
+                Err(e) => Err(e).with_context(|| format!("{id:?} isn't a (decimal) object ID")),
+            },
+        }
+    }
+
+    pub fn format(self, id: u64) -> String {
+        match self {
+            Self::Hex => format!("{id:x}"),
+            Self::Dec => id.to_string(),
+        }
+    }
+}
+
+/// The keyword Tacview uses for the event (`Event=Destroyed|...`)
+fn event_keyword(kind: &EventKind) -> &str {
+    match kind {
+        EventKind::Message => "Message",
+        EventKind::Bookmark => "Bookmark",
+        EventKind::Debug => "Debug",
+        EventKind::LeftArea => "LeftArea",
+        EventKind::Destroyed => "Destroyed",
+        EventKind::TakenOff => "TakenOff",
+        EventKind::Landed => "Landed",
+        EventKind::Timeout => "Timeout",
+        EventKind::Unknown(k) => k,
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    io::{self, prelude::*, BufWriter},
+    num::NonZeroUsize,
+};
+
+use anyhow::{bail, Result};
+use camino::Utf8Path;
+use float_ord::FloatOrd;
+use log::*;
+use tacview::record::Record;
+
+use crate::{check_version, coords::Tracker, filter::IdBase, input::Slice, Reader};
+
+/// The most cells wide and tall a grid can be,
+/// which is already far more detail than anyone will plot from a CSV
+/// (and a count for each of its cells takes 128 MiB).
+const MAX_CELLS: usize = 4096;
+
+/// Bin every object position into a `cells` × `cells` grid over the recording's
+/// bounding box, and print the count in each (non-empty) cell as CSV.
+///
+/// Row 0 is the northern edge, column 0 the western.
+pub fn heatmap(
+    acmi: &Utf8Path,
+    cells: NonZeroUsize,
+    id_base: IdBase,
+    slice: Slice,
+    decimal_comma: bool,
+) -> Result<()> {
+    let cells = cells.get();
+    if cells.checked_mul(cells).is_none() || cells > MAX_CELLS {
+        bail!("--cells {cells} is too many; it can be at most {MAX_CELLS}");
+    }
+    let mut fh = slice.open(acmi)?;
+    check_version(acmi, &mut fh, decimal_comma)?;
+
+    info!("Reading all records to find the bounding box");
+    let mut min_lat = FloatOrd(f64::INFINITY);
+    let mut max_lat = FloatOrd(f64::NEG_INFINITY);
+    let mut min_lon = FloatOrd(f64::INFINITY);
+    let mut max_lon = FloatOrd(f64::NEG_INFINITY);
+    for_each_position(Reader::new(&mut fh, decimal_comma, id_base)?, |lat, lon| {
+        min_lat = min_lat.min(FloatOrd(lat));
+        max_lat = max_lat.max(FloatOrd(lat));
+        min_lon = min_lon.min(FloatOrd(lon));
+        max_lon = max_lon.max(FloatOrd(lon));
+    })?;
+    fh.rewind()?;
+    debug!(
+        "Bounding box (relative to reference): lat {}..{}, lon {}..{}",
+        min_lat.0, max_lat.0, min_lon.0, max_lon.0
+    );
+
+    // Which cell a value falls into, given a range.
+    let bin = |v: f64, min: f64, max: f64| {
+        if max > min {
+            (((v - min) / (max - min) * cells as f64) as usize).min(cells - 1)
+        } else {
+            0
+        }
+    };
+
+    info!("Binning positions into a {cells}x{cells} grid");
+    let mut grid = vec![0u64; cells * cells];
+    for_each_position(Reader::new(&mut fh, decimal_comma, id_base)?, |lat, lon| {
+        let row = cells - 1 - bin(lat, min_lat.0, max_lat.0);
+        let col = bin(lon, min_lon.0, max_lon.0);
+        grid[row * cells + col] += 1;
+    })?;
+
+    let mut w = BufWriter::new(io::stdout().lock());
+    writeln!(w, "row,col,count")?;
+    for (i, count) in grid.iter().enumerate().filter(|(_, c)| **c > 0) {
+        writeln!(w, "{},{},{count}", i / cells, i % cells)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Call `f` with the (reference-relative) latitude and longitude
+/// of every object update that has them.
+fn for_each_position<F: FnMut(f64, f64)>(records: Reader, mut f: F) -> Result<()> {
+    let mut positions = Tracker::default();
+    for rec in records {
+        match rec? {
+            Record::Update(up) => {
+                if let Some(c) = positions.update(&up) {
+                    if let (Some(lat), Some(lon)) = (c.latitude, c.longitude) {
+                        f(lat, lon);
+                    }
+                }
+            }
+            Record::Remove(id) => positions.remove(id),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::{
+    fs::File,
+    io::{prelude::*, BufWriter},
+};
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use log::*;
+
+/// Identifies (and versions) index files
+const MAGIC: &[u8; 8] = b"ACMIIDX1";
+
+/// Maps each frame's time to its byte offset in the compressed output,
+/// so that a viewer can seek to a given time without parsing everything before it.
+///
+/// This is written as a sidecar file: the magic bytes `ACMIIDX1`, followed by
+/// one (time, offset) pair for each frame, as a little-endian `f64` and `u64`.
+/// Offsets count from the start of the output, header included.
+#[derive(Debug, Default)]
+pub struct FrameIndex {
+    entries: Vec<(f64, u64)>,
+}
+
+impl FrameIndex {
+    pub fn push(&mut self, time: f64, offset: u64) {
+        self.entries.push((time, offset));
+    }
+
+    pub fn write(&self, path: &Utf8Path) -> Result<()> {
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("Couldn't create index {path}"))?,
+        );
+        w.write_all(MAGIC)?;
+        for (time, offset) in &self.entries {
+            w.write_all(&time.to_le_bytes())?;
+            w.write_all(&offset.to_le_bytes())?;
+        }
+        w.flush()
+            .with_context(|| format!("Couldn't write index {path}"))?;
+        debug!("Wrote {} frames to index {path}", self.entries.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs::File,
+    io::{self, prelude::*, SeekFrom},
+};
+
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path;
+
+/// Where in its file an ACMI is, for ACMIs embedded in bigger files
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Slice {
+    pub offset: u64,
+    /// If unset, the ACMI runs to the end of the file.
+    pub length: Option<u64>,
+}
+
+impl Slice {
+    pub fn open(self, path: &Utf8Path) -> Result<Input> {
+        let mut file = File::open(path).context("Couldn't open ACMI")?;
+        let file_len = file.metadata()?.len();
+        if self.offset > file_len {
+            bail!(
+                "--input-offset {} is past the end of {path} ({file_len} bytes)",
+                self.offset
+            );
+        }
+        let len = match self.length {
+            Some(l) => l.min(file_len - self.offset),
+            None => file_len - self.offset,
+        };
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(Input {
+            file,
+            start: self.offset,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+/// An ACMI file, or just the slice of a file that has the ACMI in it.
+///
+/// Reading and seeking are relative to the start of the slice,
+/// so everything downstream can treat it like a whole file.
+pub struct Input {
+    file: File,
+    /// Where the slice starts in the file
+    start: u64,
+    len: u64,
+    /// Where we are in the slice
+    pos: u64,
+}
+
+impl Input {
+    /// How many bytes are in the slice
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Is this a ZIP file? (Or is it plain text?)
+    ///
+    /// We check the magic bytes instead of the file name
+    /// since an embedded ACMI doesn't have a name.
+    pub fn is_zipped(&mut self) -> io::Result<bool> {
+        let here = self.pos;
+        let mut magic = [0u8; 4];
+        let mut read = 0;
+        while read < magic.len() {
+            match self.read(&mut magic[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        self.seek(SeekFrom::Start(here))?;
+        Ok(read == magic.len() && magic == *b"PK\x03\x04")
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.file.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let to = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        let to = to.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of ACMI")
+        })?;
+        self.file.seek(SeekFrom::Start(self.start + to))?;
+        self.pos = to;
+        Ok(to)
+    }
+}
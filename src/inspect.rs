@@ -0,0 +1,283 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, prelude::*, BufWriter},
+};
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use log::*;
+use rustc_hash::FxHashSet;
+use tacview::record::{GlobalProperty, Record};
+
+use crate::{check_version, filter::IdBase, input::Slice, meta::HeaderCache, Reader};
+
+/// Print the ACMI's global properties, then (unless `header_only`)
+/// a summary of everything after them.
+///
+/// With `header_only`, we stop at the first record that isn't
+/// a global property, so this only reads as much of the file as its header.
+/// With `cache` too, we save that header next to the ACMI and reuse it next time.
+/// With `histogram`, also print how many object updates each frame has.
+pub fn inspect(
+    acmi: &Utf8Path,
+    header_only: bool,
+    cache: bool,
+    histogram: bool,
+    id_base: IdBase,
+    slice: Slice,
+    decimal_comma: bool,
+) -> Result<()> {
+    let cache = if cache {
+        Some(HeaderCache::new(acmi, slice, decimal_comma).context("Couldn't stat ACMI")?)
+    } else {
+        None
+    };
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    if header_only {
+        if let Some(header) = cache.as_ref().and_then(HeaderCache::load) {
+            out.write_all(header.as_bytes())?;
+            out.flush()?;
+            return Ok(());
+        }
+    }
+
+    let mut fh = slice.open(acmi)?;
+    check_version(acmi, &mut fh, decimal_comma)?;
+    let mut reader = Reader::new(&mut fh, decimal_comma, id_base)?;
+
+    let mut header = String::new();
+    // The first record after the header, if there is one.
+    let mut first = None;
+    for rec in &mut reader {
+        match rec? {
+            Record::GlobalProperty(gp) => {
+                let (key, value) = key_value(gp);
+                writeln!(header, "{key}: {value}")?;
+            }
+            not_global => {
+                first = Some(not_global);
+                break;
+            }
+        }
+    }
+    out.write_all(header.as_bytes())?;
+    if let Some(c) = &cache {
+        c.store(&header);
+    }
+
+    if header_only {
+        debug!("Stopping at the end of the header");
+        out.flush()?;
+        return Ok(());
+    }
+
+    let mut summary = Summary::default();
+    if let Some(rec) = first {
+        summary.add(rec);
+    }
+    for rec in reader {
+        summary.add(rec?);
+    }
+
+    let (start, end) = summary.span.unwrap_or_default();
+    writeln!(out, "Frames: {}", summary.frames)?;
+    writeln!(out, "Duration: {:.2}s", end - start)?;
+    writeln!(out, "Objects: {}", summary.objects.len())?;
+    writeln!(out, "Updates: {}", summary.updates)?;
+    writeln!(out, "Removals: {}", summary.removes)?;
+    writeln!(out, "Events: {}", summary.events)?;
+    if histogram {
+        summary.per_frame.finish();
+        summary.per_frame.print(&mut out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Print the ACMI's global properties as shell variable assignments,
+/// like `ACMI_REFERENCE_TIME='2024-01-01T12:00:00Z'`, for `eval`.
+///
+/// Like `inspect --header-only`, this stops at the first record after the header.
+pub fn env(acmi: &Utf8Path, id_base: IdBase, slice: Slice, decimal_comma: bool) -> Result<()> {
+    let mut fh = slice.open(acmi)?;
+    check_version(acmi, &mut fh, decimal_comma)?;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for rec in Reader::new(&mut fh, decimal_comma, id_base)? {
+        let gp = match rec? {
+            Record::GlobalProperty(gp) => gp,
+            _ => break,
+        };
+        let (key, value) = key_value(gp);
+        writeln!(out, "{}={}", env_name(&key), shell_quote(&value))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// `ReferenceTime` -> `ACMI_REFERENCE_TIME`
+///
+/// Anything that can't be in a shell variable name becomes an underscore.
+fn env_name(key: &str) -> String {
+    let mut name = String::from("ACMI_");
+    let mut prev_lower = false;
+    for c in key.chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            name.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_uppercase());
+        } else {
+            name.push('_');
+        }
+    }
+    name
+}
+
+/// Single-quote the value, so the shell takes it literally
+/// (newlines, dollar signs, and all).
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Counts of everything after the header
+#[derive(Debug, Default)]
+struct Summary {
+    frames: u64,
+    /// The first and last frame times
+    span: Option<(f64, f64)>,
+    objects: FxHashSet<u64>,
+    updates: u64,
+    removes: u64,
+    events: u64,
+    per_frame: UpdatesPerFrame,
+}
+
+impl Summary {
+    fn add(&mut self, rec: Record) {
+        match rec {
+            Record::Frame(t) => {
+                self.frames += 1;
+                self.per_frame.frame();
+                let (start, _) = self.span.unwrap_or((t, t));
+                self.span = Some((start, t));
+            }
+            Record::Update(up) => {
+                self.objects.insert(up.id);
+                self.updates += 1;
+                self.per_frame.update();
+            }
+            Record::Remove(_) => self.removes += 1,
+            Record::Event(_) => self.events += 1,
+            Record::GlobalProperty(_) => {}
+        }
+    }
+}
+
+/// Counts how many object updates come between each frame and the next
+#[derive(Debug, Default)]
+struct UpdatesPerFrame {
+    /// Updates so far in the current frame (if we've seen one)
+    current: Option<u64>,
+    /// How many frames had each number of updates
+    frames: BTreeMap<u64, u64>,
+}
+
+impl UpdatesPerFrame {
+    fn frame(&mut self) {
+        self.finish();
+        self.current = Some(0);
+    }
+
+    fn update(&mut self) {
+        if let Some(c) = &mut self.current {
+            *c += 1;
+        }
+    }
+
+    /// Count the frame in progress.
+    fn finish(&mut self) {
+        if let Some(c) = self.current.take() {
+            *self.frames.entry(c).or_default() += 1;
+        }
+    }
+
+    /// The smallest update count that at least `p` of the frames are at or below
+    fn percentile(&self, p: f64) -> u64 {
+        let total: u64 = self.frames.values().sum();
+        let rank = ((p * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (updates, frames) in &self.frames {
+            seen += frames;
+            if seen >= rank {
+                return *updates;
+            }
+        }
+        0
+    }
+
+    fn print<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (min, max) = match (self.frames.keys().next(), self.frames.keys().last()) {
+            (Some(min), Some(max)) => (*min, *max),
+            _ => return writeln!(w, "Updates per frame: no frames"),
+        };
+        writeln!(
+            w,
+            "Updates per frame: min {min}, median {}, p95 {}, max {max}",
+            self.percentile(0.5),
+            self.percentile(0.95)
+        )?;
+
+        // Bucket by powers of two: 0, 1, 2-3, 4-7...
+        let mut buckets: Vec<(u64, u64, u64)> = vec![];
+        for (updates, frames) in &self.frames {
+            let (lo, hi) = match updates {
+                0 => (0, 0),
+                n => {
+                    let lo = 1 << (63 - n.leading_zeros());
+                    (lo, lo * 2 - 1)
+                }
+            };
+            match buckets.last_mut() {
+                Some((l, _, count)) if *l == lo => *count += frames,
+                _ => buckets.push((lo, hi, *frames)),
+            }
+        }
+
+        const WIDTH: u64 = 40;
+        let most = buckets.iter().map(|(_, _, c)| *c).max().unwrap_or(1);
+        for (lo, hi, count) in buckets {
+            let label = if lo == hi {
+                lo.to_string()
+            } else {
+                format!("{lo}-{hi}")
+            };
+            let bar = "#".repeat(((count * WIDTH).div_ceil(most)) as usize);
+            writeln!(w, "{label:>12} | {bar} {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The property's ACMI key and its value
+fn key_value(gp: GlobalProperty) -> (String, String) {
+    let (key, value) = match gp {
+        GlobalProperty::DataSource(v) => ("DataSource", v),
+        GlobalProperty::DataRecorder(v) => ("DataRecorder", v),
+        GlobalProperty::ReferenceTime(v) => ("ReferenceTime", v),
+        GlobalProperty::RecordingTime(v) => ("RecordingTime", v),
+        GlobalProperty::Author(v) => ("Author", v),
+        GlobalProperty::Title(v) => ("Title", v),
+        GlobalProperty::Category(v) => ("Category", v),
+        GlobalProperty::Briefing(v) => ("Briefing", v),
+        GlobalProperty::Debriefing(v) => ("Debriefing", v),
+        GlobalProperty::Comments(v) => ("Comments", v),
+        GlobalProperty::ReferenceLongitude(v) => ("ReferenceLongitude", v.to_string()),
+        GlobalProperty::ReferenceLatitude(v) => ("ReferenceLatitude", v.to_string()),
+        GlobalProperty::Unknown(k, v) => return (k, v),
+    };
+    (key.to_owned(), value)
+}
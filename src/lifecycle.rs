@@ -0,0 +1,155 @@
+use std::{
+    fmt::Write as _,
+    io::{self, prelude::*, BufWriter},
+};
+
+use anyhow::Result;
+use camino::Utf8Path;
+use rustc_hash::FxHashMap;
+use tacview::record::{Property, Record};
+
+use crate::{check_version, filter::IdBase, input::Slice, Reader};
+
+/// When an object was added and removed, and what it was
+#[derive(Debug, Default)]
+struct Life {
+    id: u64,
+    spawned: f64,
+    /// None if it was still around at the end of the recording
+    despawned: Option<f64>,
+    name: String,
+    kind: String,
+}
+
+/// Print when each object appeared and disappeared, in order of appearance,
+/// as a table or (with `json`) a JSON array.
+pub fn lifecycle(
+    acmi: &Utf8Path,
+    json: bool,
+    id_base: IdBase,
+    slice: Slice,
+    decimal_comma: bool,
+) -> Result<()> {
+    let mut fh = slice.open(acmi)?;
+    check_version(acmi, &mut fh, decimal_comma)?;
+
+    let mut now = 0.0;
+    let mut live: FxHashMap<u64, Life> = FxHashMap::default();
+    let mut lives = vec![];
+    for rec in Reader::new(&mut fh, decimal_comma, id_base)? {
+        match rec? {
+            Record::Frame(t) => now = t,
+            Record::Update(up) => {
+                let life = live.entry(up.id).or_insert_with(|| Life {
+                    id: up.id,
+                    spawned: now,
+                    ..Life::default()
+                });
+                for prop in up.props {
+                    match prop {
+                        Property::Name(n) => life.name = n,
+                        Property::Type(t) => life.kind = t,
+                        _ => {}
+                    }
+                }
+            }
+            Record::Remove(id) => {
+                if let Some(mut life) = live.remove(&id) {
+                    life.despawned = Some(now);
+                    lives.push(life);
+                }
+            }
+            Record::GlobalProperty(_) | Record::Event(_) => {}
+        }
+    }
+    lives.extend(live.into_values());
+    lives.sort_by(|a, b| a.spawned.total_cmp(&b.spawned).then(a.id.cmp(&b.id)));
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    if json {
+        write_json(&mut out, &lives, id_base)?;
+    } else {
+        write_table(&mut out, &lives, id_base)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_table<W: Write>(w: &mut W, lives: &[Life], id_base: IdBase) -> io::Result<()> {
+    let ids: Vec<String> = lives.iter().map(|l| id_base.format(l.id)).collect();
+    let id_width = ids.iter().map(String::len).max().unwrap_or_default().max(2);
+    let name_width = lives
+        .iter()
+        .map(|l| l.name.chars().count())
+        .max()
+        .unwrap_or_default()
+        .max(4);
+
+    writeln!(
+        w,
+        "{:id_width$}  {:>8}  {:>9}  {:name_width$}  TYPE",
+        "ID", "SPAWNED", "DESPAWNED", "NAME"
+    )?;
+    for (life, id) in lives.iter().zip(&ids) {
+        let despawned = life.despawned.map_or_else(|| "-".to_owned(), clock);
+        writeln!(
+            w,
+            "{id:id_width$}  {:>8}  {despawned:>9}  {:name_width$}  {}",
+            clock(life.spawned),
+            life.name,
+            life.kind
+        )?;
+    }
+    Ok(())
+}
+
+fn write_json<W: Write>(w: &mut W, lives: &[Life], id_base: IdBase) -> io::Result<()> {
+    writeln!(w, "[")?;
+    for (i, life) in lives.iter().enumerate() {
+        let despawned = life
+            .despawned
+            .map_or_else(|| "null".to_owned(), |t| t.to_string());
+        let comma = if i + 1 < lives.len() { "," } else { "" };
+        writeln!(
+            w,
+            "  {{\"id\": \"{}\", \"spawned\": {}, \"despawned\": {despawned}, \
+             \"name\": {}, \"type\": {}}}{comma}",
+            id_base.format(life.id),
+            life.spawned,
+            json_string(&life.name),
+            json_string(&life.kind)
+        )?;
+    }
+    writeln!(w, "]")
+}
+
+/// Seconds into the recording as `MM:SS`, or `H:MM:SS` past an hour
+fn clock(t: f64) -> String {
+    let secs = t.max(0.0) as u64;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m:02}:{s:02}")
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,98 @@
+use std::num::NonZeroUsize;
+
+use log::*;
+use rustc_hash::FxHashSet;
+use tacview::record::Record;
+
+use crate::{filter::IdBase, transform::RecordTransform};
+
+/// Keeps at most `max` objects live at once.
+///
+/// A corrupt exporter can produce millions of phantom objects,
+/// and everything that tracks per-object state grows with them.
+/// Once the limit is hit, no new objects are let in,
+/// even if some of the ones we have are removed later.
+pub struct ObjectLimit {
+    max: NonZeroUsize,
+    id_base: IdBase,
+    live: FxHashSet<u64>,
+    /// The object that first didn't fit
+    tripped_by: Option<u64>,
+    /// Objects we turned away that haven't been removed yet,
+    /// so we can drop their removals too
+    turned_away: FxHashSet<u64>,
+    /// How many updates we dropped for objects that didn't fit
+    dropped: u64,
+}
+
+impl ObjectLimit {
+    pub fn new(max: NonZeroUsize, id_base: IdBase) -> Self {
+        Self {
+            max,
+            id_base,
+            live: FxHashSet::default(),
+            tripped_by: None,
+            turned_away: FxHashSet::default(),
+            dropped: 0,
+        }
+    }
+
+    /// Should this record be kept?
+    /// Only false for records about objects that didn't fit.
+    /// Removals of objects we never saw (or already removed) are kept;
+    /// the writer skips those on its own.
+    pub fn admit(&mut self, rec: &Record) -> bool {
+        match rec {
+            Record::Update(up) => {
+                if self.live.contains(&up.id) {
+                    return true;
+                }
+                if self.tripped_by.is_none() && self.live.len() < self.max.get() {
+                    self.live.insert(up.id);
+                    return true;
+                }
+                if self.tripped_by.is_none() {
+                    self.tripped_by = Some(up.id);
+                }
+                self.turned_away.insert(up.id);
+                self.dropped += 1;
+                false
+            }
+            Record::Remove(id) => {
+                self.live.remove(id);
+                !self.turned_away.remove(id)
+            }
+            _ => true,
+        }
+    }
+
+    /// Why we stopped, for logs and errors
+    pub fn describe(&self) -> String {
+        format!(
+            "object {} would make {} live objects, past --max-objects {}",
+            self.id_base.format(self.tripped_by.unwrap_or_default()),
+            self.max.get() + 1,
+            self.max
+        )
+    }
+}
+
+impl RecordTransform for ObjectLimit {
+    fn transform(&mut self, rec: Record, out: &mut Vec<Record>) {
+        let tripped = self.tripped_by.is_some();
+        if self.admit(&rec) {
+            out.push(rec);
+        } else if !tripped {
+            warn!("{}; dropping any more new objects", self.describe());
+        }
+    }
+
+    fn flush(&mut self, _out: &mut Vec<Record>) {
+        if self.dropped > 0 {
+            warn!(
+                "Dropped {} updates to objects past --max-objects",
+                self.dropped
+            );
+        }
+    }
+}
@@ -1,33 +1,555 @@
 use std::{
-    fs::File,
+    cell::Cell,
+    env,
+    fs::{self, File},
     io::{self, prelude::*, BufReader, BufWriter},
     mem::{discriminant, Discriminant},
+    num::NonZeroUsize,
+    process,
+    rc::Rc,
+    sync::OnceLock,
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context, Result};
 use bytesize::ByteSize;
 use camino::{Utf8Path, Utf8PathBuf};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossbeam::channel::{bounded, Receiver};
 use float_ord::FloatOrd;
 use log::*;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use simplelog::*;
 use tacview::{
     record::{Coords, GlobalProperty, Property, Record, Update},
     ParseError,
 };
+use tracing::{info_span, trace_span, Span};
+
+mod archive;
+mod breakdown;
+mod coords;
+mod decimal;
+mod filter;
+mod heatmap;
+mod index;
+mod input;
+mod inspect;
+mod lifecycle;
+mod limit;
+mod merge;
+mod meta;
+mod metrics;
+mod missions;
+mod order;
+mod parked;
+mod rename;
+mod replay;
+mod selftest;
+mod states;
+mod synth;
+mod template;
+mod trace;
+mod transform;
+mod validate;
+mod watch;
+
+use breakdown::{Category, Originals, SizeBreakdown};
+use decimal::DecimalCommas;
+use filter::{Filter, IdBase};
+use index::FrameIndex;
+use input::{Input, Slice};
+use limit::ObjectLimit;
+use metrics::Stats;
+use parked::{JitterFilter, ParkedFilter};
+use states::ObjectStates;
+use template::OutputTemplate;
+use transform::{CloseDangling, Pipeline, Precision, Reposition, StageContext, TimeScale};
+use validate::Validator;
 
 #[derive(Debug, Parser)]
+#[clap(
+    subcommand_negates_reqs = true,
+    after_help = "Options with an [env: ...] can also be set with that environment variable. \
+    Flags given on the command line take precedence."
+)]
 struct Args {
     /// Verbosity (-v, -vv, -vvv, etc.)
-    #[clap(short, long, parse(from_occurrences))]
+    #[clap(short, long, global = true, parse(from_occurrences))]
     verbose: u8,
 
-    #[clap(short, long, arg_enum, default_value = "auto")]
+    /// Only log errors (-q), or nothing at all (-qq)
+    #[clap(
+        short,
+        long,
+        global = true,
+        parse(from_occurrences),
+        conflicts_with = "verbose"
+    )]
+    quiet: u8,
+
+    /// Color log output. `auto` only colors when stderr is a terminal,
+    /// and never when NO_COLOR or CI is set or TERM=dumb.
+    #[clap(
+        short,
+        long,
+        global = true,
+        arg_enum,
+        default_value = "auto",
+        env = "ACMI_COMPRESSOR_COLOR"
+    )]
     color: Color,
 
-    acmi: Utf8PathBuf,
+    /// How object IDs are written, in options like --only and in our output
+    #[clap(
+        long,
+        global = true,
+        arg_enum,
+        value_name = "BASE",
+        default_value = "hex",
+        env = "ACMI_COMPRESSOR_ID_BASE"
+    )]
+    id_base: IdBase,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Remember the state of at most N objects, forgetting the least recently updated.
+    ///
+    /// Forgotten objects have their next update written in full,
+    /// so this trades compression for bounded memory use.
+    #[clap(long, value_name = "N", env = "ACMI_COMPRESSOR_STATE_CAP")]
+    state_cap: Option<NonZeroUsize>,
+
+    /// Give up if more than N objects are live at once.
+    ///
+    /// A safety valve for corrupt files full of phantom objects,
+    /// which would otherwise grow per-object state until we run out of memory.
+    #[clap(long, value_name = "N", env = "ACMI_COMPRESSOR_MAX_OBJECTS")]
+    max_objects: Option<NonZeroUsize>,
+
+    /// Instead of giving up at --max-objects, warn and drop any more new objects.
+    #[clap(long, requires = "max-objects")]
+    lenient: bool,
+
+    /// Drop updates for objects that don't move, like parked aircraft.
+    ///
+    /// Once an object has had --static-updates updates within --static-threshold
+    /// meters of where it started, its later positions are dropped until it moves.
+    /// (So the first position dropped is in update --static-updates + 1.)
+    /// Its add and removal, and changes to its other properties, are kept.
+    #[clap(long)]
+    drop_static: bool,
+
+    /// How far (in meters) an object can wander and still be considered static
+    #[clap(
+        long,
+        value_name = "METERS",
+        default_value = "1.0",
+        env = "ACMI_COMPRESSOR_STATIC_THRESHOLD"
+    )]
+    static_threshold: f64,
+
+    /// How many updates an object gets written in full before it can be considered static
+    #[clap(
+        long,
+        value_name = "K",
+        default_value = "10",
+        env = "ACMI_COMPRESSOR_STATIC_UPDATES"
+    )]
+    static_updates: u32,
+
+    /// Hold back position updates that moved less than this many meters
+    /// since the last one we kept.
+    ///
+    /// Unlike --drop-static, objects still get an update at least every
+    /// --dedup-max-hold seconds, so nothing's ever more than that out of date.
+    #[clap(long, value_name = "METERS", env = "ACMI_COMPRESSOR_DEDUP_EPSILON")]
+    dedup_epsilon: Option<f64>,
+
+    /// With --dedup-epsilon, let an object's position through at least this often
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value = "10.0",
+        env = "ACMI_COMPRESSOR_DEDUP_MAX_HOLD"
+    )]
+    dedup_max_hold: f64,
+
+    /// Multiply every frame time by this factor.
+    ///
+    /// 0.5 halves the recording's duration, 2.0 doubles it.
+    /// Objects will move impossibly fast (or slow) for their recorded speeds,
+    /// but this is handy for feeding non-realtime analysis tools.
+    /// (Tacview events don't carry their own times; they move with their frames.)
+    #[clap(
+        long,
+        value_name = "FACTOR",
+        default_value = "1.0",
+        env = "ACMI_COMPRESSOR_TIME_SCALE"
+    )]
+    time_scale: f64,
+
+    /// Round coordinates (positions and angles) to this many decimal places.
+    ///
+    /// Shifting coordinates to the new reference point leaves float noise
+    /// like 0.30000000000000004 in their text; rounding tidies that up.
+    /// Six places of latitude and longitude is about 10cm.
+    #[clap(long, value_name = "PLACES", env = "ACMI_COMPRESSOR_PRECISION")]
+    precision: Option<u8>,
+
+    /// Round individual fields to their own number of decimal places,
+    /// like `LatLon=6,Alt=0,Heading=1`, overriding --precision for those fields.
+    ///
+    /// Takes coordinate fields (LatLon, Lon, Lat, Alt, UV, Roll, Pitch, Yaw, Heading)
+    /// and numeric properties (IAS, CAS, TAS, Mach, AOA, AOS, AGL, HDG, HDM, Throttle).
+    /// Fields without a precision are left alone.
+    #[clap(long, value_name = "SPEC", env = "ACMI_COMPRESSOR_PRECISION_SPEC")]
+    precision_spec: Option<String>,
+
+    /// The input uses commas as decimal separators (like some European-locale exports).
+    ///
+    /// Rewrite them to dots before parsing,
+    /// leaving the commas that separate fields alone.
+    #[clap(long, global = true)]
+    decimal_comma: bool,
+
+    /// Read the ACMI starting this many bytes into the file,
+    /// for ACMIs embedded in a bigger file (like a game's replay bundle).
+    ///
+    /// Whether it's zipped is worked out from the slice's contents, not the file name.
+    #[clap(long, global = true, value_name = "BYTES", default_value = "0")]
+    input_offset: u64,
+
+    /// Only read this many bytes of the file (after --input-offset).
+    ///
+    /// Without it, the ACMI runs to the end of the file.
+    #[clap(long, global = true, value_name = "BYTES")]
+    input_length: Option<u64>,
+
+    /// Write how long each phase (scan, read, parse, transform, write...) took
+    /// to this file as folded stacks, for inferno-flamegraph and the like.
+    ///
+    /// Without it, those phases are logged (at trace level, with -vvv).
+    #[clap(long, global = true, value_name = "PATH")]
+    trace_file: Option<Utf8PathBuf>,
+
+    /// After compressing, log how much each kind of record
+    /// (global, frame, update, remove, event) shrank.
+    #[clap(long)]
+    size_breakdown: bool,
+
+    /// Don't write anything; just print how much smaller each input's updates would get.
+    ///
+    /// Runs the same compression (including any lossy options like --drop-static)
+    /// into a sink, then reports the share of update bytes it removed.
+    #[clap(long, conflicts_with_all = &["output-template", "emit-index"])]
+    dedup_report: bool,
+
+    /// Don't write anything; just predict how big each input's output would be.
+    ///
+    /// Compresses the first --estimate-records records (with all the same options)
+    /// into a sink, then scales that up by how much of the input they were.
+    #[clap(
+        long,
+        conflicts_with_all = &["output-template", "emit-index", "dedup-report"]
+    )]
+    estimate: bool,
+
+    /// How many records --estimate samples
+    #[clap(long, value_name = "N", default_value = "100000")]
+    estimate_records: usize,
+
+    /// Write each update's properties in a fixed order
+    /// (position first, then identity, appearance, and flight data).
+    ///
+    /// Some exporters shuffle properties between updates, which makes
+    /// otherwise-identical lines differ and compress worse.
+    /// This doesn't change what the ACMI means.
+    #[clap(long)]
+    normalize_property_order: bool,
+
+    /// Only keep the objects with these IDs
+    #[clap(long, value_name = "ID")]
+    only: Vec<String>,
+
+    /// With --only, also keep the children (and parents) of those objects,
+    /// like the missiles a launcher fires.
+    ///
+    /// Families are found from objects' Parent properties,
+    /// which requires an extra pass over the ACMI. No other references
+    /// (like FocusedTarget or LockedTarget) are followed.
+    #[clap(long, requires = "only")]
+    include_children: bool,
+
+    /// Keep a random (but repeatable, see --seed) N of the objects, dropping the rest
+    #[clap(long, value_name = "N", conflicts_with = "only")]
+    sample_objects: Option<usize>,
+
+    /// Seeds anything random, like which objects --sample-objects picks.
+    ///
+    /// Without one, we roll a seed and log it, so the run can be repeated.
+    #[clap(long, global = true, env = "ACMI_COMPRESSOR_SEED")]
+    seed: Option<u64>,
+
+    /// Only keep events of this type (by Tacview keyword, e.g. Destroyed).
+    /// Can be given multiple times.
+    #[clap(long, value_name = "NAME")]
+    event_type: Vec<String>,
+
+    /// Drop these kinds of records entirely
+    #[clap(long, value_name = "KIND", arg_enum, conflicts_with = "event-type")]
+    drop: Vec<DropKind>,
+
+    /// Drop the global object (ID 0): global properties after the header, and all events.
+    ///
+    /// Otherwise it's always kept, even by --only.
+    /// The header is kept either way, since the recording needs it.
+    #[clap(long, conflicts_with_all = &["keep-global", "event-type", "drop"])]
+    drop_global: bool,
+
+    /// Keep only the global object (ID 0), dropping every other object
+    #[clap(long, conflicts_with_all = &["only", "sample-objects"])]
+    keep_global: bool,
+
+    /// Rename objects, using a CSV of `id,name` or `id,name,pilot` lines.
+    ///
+    /// IDs are written in --id-base. Objects not in the file keep their names.
+    #[clap(long, value_name = "CSV")]
+    rename: Option<Utf8PathBuf>,
+
+    /// Drop coordinates that are physically impossible
+    /// (see the validate subcommand) instead of passing them through.
+    #[clap(long)]
+    repair: bool,
+
+    /// Remove every object still around at the end of the recording.
+    ///
+    /// Tacview doesn't need this, but some tools expect every object
+    /// to eventually be removed.
+    #[clap(long)]
+    close_dangling: bool,
+
+    /// The fastest (in m/s) an object can plausibly move between frames
+    /// before we think it teleported
+    #[clap(long, global = true, value_name = "M/S", default_value = "3000")]
+    max_speed: f64,
+
+    /// Transform records with this pipeline of stages, run in order.
+    ///
+    /// Stages are separated by `|`, e.g. `only=1a,2b | repair | precision=6`,
+    /// and work like the options of the same names (see the list-transforms subcommand).
+    /// They run after those options, and before writing drops unchanged properties.
+    #[clap(long, value_name = "STAGES")]
+    pipe: Option<String>,
+
+    /// Write each compressed ACMI to a path built from this template
+    /// instead of stdout. Required when compressing more than one file.
+    ///
+    /// Placeholders are `{dir}` (the input's directory), `{stem}` (its name
+    /// without extension), `{ext}` (`acmi` or `zip.acmi`), and `{format}`
+    /// (the output's extension), e.g. `compressed/{dir}/{stem}.{format}`.
+    /// Parent directories are created as needed.
+    #[clap(
+        short,
+        long,
+        value_name = "TEMPLATE",
+        env = "ACMI_COMPRESSOR_OUTPUT_TEMPLATE"
+    )]
+    output_template: Option<String>,
+
+    /// Write a sidecar index of each frame's time and byte offset
+    /// in the compressed output, so viewers can seek.
+    ///
+    /// Takes the same placeholders as --output-template.
+    /// The index is the bytes `ACMIIDX1`, then a little-endian
+    /// f64 time and u64 offset for each frame.
+    #[clap(long, value_name = "PATH")]
+    emit_index: Option<String>,
+
+    /// After compressing, write totals (bytes in and out, records, and run time)
+    /// to this file as Prometheus metrics, for node_exporter's textfile collector
+    #[clap(long, value_name = "PATH")]
+    metrics_file: Option<Utf8PathBuf>,
+
+    /// When compressing several ACMIs, stop at the first one that fails.
+    ///
+    /// Otherwise we compress the rest, then list every failure
+    /// (and exit with an error) at the end.
+    #[clap(long)]
+    fail_fast: bool,
+
+    /// Compress every ACMI in this tar archive (gzipped or not)
+    /// instead of ACMIs given on the command line.
+    ///
+    /// Outputs are named with --output-template, using each member's path
+    /// in the archive, so `{dir}` mirrors the archive's directories.
+    #[clap(
+        long,
+        value_name = "TAR",
+        requires = "output-template",
+        conflicts_with_all = &["acmi", "estimate"]
+    )]
+    archive: Option<Utf8PathBuf>,
+
+    #[clap(required_unless_present = "archive")]
+    acmi: Vec<Utf8PathBuf>,
+}
+
+impl Args {
+    /// --seed, or one rolled (and logged) the first time something asks for it
+    fn seed(&self) -> u64 {
+        static ROLLED: OnceLock<u64> = OnceLock::new();
+        self.seed.unwrap_or_else(|| {
+            *ROLLED.get_or_init(|| {
+                let seed = filter::roll_seed();
+                warn!("Using seed {seed} (pass --seed {seed} to repeat this run)");
+                seed
+            })
+        })
+    }
+
+    /// Which part of each input file to read
+    fn slice(&self) -> Slice {
+        Slice {
+            offset: self.input_offset,
+            length: self.input_length,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Count object positions in an N×N grid over the recording's bounding box.
+    ///
+    /// Prints CSV (row,col,count) of the non-empty cells,
+    /// with row 0 at the north edge and column 0 at the west.
+    ToHeatmap {
+        /// How many cells wide and tall the grid is
+        #[clap(long, value_name = "N", default_value = "100")]
+        cells: NonZeroUsize,
+
+        acmi: Utf8PathBuf,
+    },
+    /// Check object updates for impossible values.
+    ///
+    /// Flags latitudes outside ±90, longitudes outside ±180,
+    /// altitudes below -500m or above 100km, and objects moving faster
+    /// than --max-speed between frames. Exits with an error if any are found.
+    /// Also lists objects that are never removed, though that isn't an error.
+    Validate {
+        /// Only check the header, stopping before the first frame.
+        ///
+        /// Checks FileType and FileVersion, that there's a well-formed ReferenceTime,
+        /// and that the reference point is a real latitude and longitude.
+        #[clap(long)]
+        header_only: bool,
+
+        acmi: Utf8PathBuf,
+    },
+    /// Print the ACMI's header (global properties) and a summary of its contents.
+    Inspect {
+        /// Only print the header, stopping before the first frame.
+        ///
+        /// This reads only as much of the file as its header,
+        /// so it's quick even for huge recordings.
+        #[clap(long)]
+        header_only: bool,
+
+        /// Cache the header in `<ACMI>.acmi-meta` and reuse it for --header-only,
+        /// as long as the ACMI hasn't changed.
+        #[clap(long)]
+        cache: bool,
+
+        /// Also print a histogram of how many object updates each frame has
+        #[clap(long, conflicts_with = "header-only")]
+        records_per_frame_histogram: bool,
+
+        acmi: Utf8PathBuf,
+    },
+    /// List when each object appeared and disappeared, in order of appearance,
+    /// with its name and type.
+    ///
+    /// Not to be confused with Tacview events (Destroyed, Message...),
+    /// which --event-type filters. Objects never removed have no despawn time.
+    Events {
+        /// Print a JSON array instead of a table
+        #[clap(long)]
+        json: bool,
+
+        acmi: Utf8PathBuf,
+    },
+    /// List the stages --pipe takes.
+    ListTransforms,
+    /// Compress a synthetic recording and check that nothing was lost,
+    /// to make sure this build works without needing an ACMI on hand.
+    ///
+    /// Prints PASS or FAIL (and exits with an error on failure).
+    Selftest,
+    /// Watch a directory, compressing each new ACMI that appears in it.
+    ///
+    /// Files are compressed once their size and modification time stop changing
+    /// between polls, using the same options as compressing them directly.
+    /// Output goes to --output-template, or `{dir}/{stem}.compressed.{format}`.
+    /// ACMIs already in the directory are left alone. Runs until killed.
+    Watch {
+        /// How often to check the directory, in seconds
+        #[clap(long, value_name = "SECONDS", default_value = "5")]
+        interval: f64,
+
+        /// Delete each ACMI once it's compressed
+        #[clap(long)]
+        remove_source: bool,
+
+        dir: Utf8PathBuf,
+    },
+    /// Split an ACMI that several recordings were appended into
+    /// (each with its own FileType header) into one file per mission.
+    ///
+    /// Missions are written uncompressed as `{stem}.1.acmi`, `{stem}.2.acmi`, etc.
+    /// and can then be compressed like any other ACMI.
+    SplitMissions {
+        /// Where to write the missions
+        #[clap(long, value_name = "DIR")]
+        out_dir: Utf8PathBuf,
+
+        acmi: Utf8PathBuf,
+    },
+    /// Print the ACMI's header as shell variables, for `eval $(acmi-compress env foo.acmi)`.
+    ///
+    /// Each global property becomes a line like `ACMI_REFERENCE_TIME='...'`,
+    /// with the value single-quoted.
+    Env { acmi: Utf8PathBuf },
+    /// Join recordings end to end into one ACMI, written to stdout.
+    ///
+    /// Each file after the first is shifted to start where the last one ended,
+    /// and objects that carry on from one file into the next stay the same object.
+    /// The output is uncompressed; compress it like any other ACMI.
+    Merge {
+        #[clap(required = true, min_values = 2)]
+        acmi: Vec<Utf8PathBuf>,
+    },
+    /// Write a compact summary of every object for custom viewers:
+    /// its spawn and despawn times, its type, and a downsampled track.
+    ///
+    /// The file is a little-endian binary layout starting with `ACMIRPL1`;
+    /// see src/replay.rs for the details.
+    ExportIndex {
+        /// Where to write the index
+        #[clap(long, value_name = "PATH")]
+        out: Utf8PathBuf,
+
+        /// Keep at most this many points of each object's track
+        #[clap(long, value_name = "N", default_value = "100")]
+        polyline_points: NonZeroUsize,
+
+        acmi: Utf8PathBuf,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ArgEnum)]
+enum DropKind {
+    Event,
 }
 
 #[derive(Debug, Copy, Clone, clap::ArgEnum)]
@@ -37,44 +559,170 @@ enum Color {
     Never,
 }
 
+/// Parses records from an ACMI, noting where we are in it
+/// so that parse errors can say where they happened.
+struct Reader<'a> {
+    source: Source<'a>,
+    /// How many records we've parsed
+    records: u64,
+    /// The last frame time we parsed
+    frame: Option<f64>,
+    /// The last object we parsed an update for
+    object: Option<u64>,
+    /// How to print that object's ID
+    id_base: IdBase,
+}
+
+/// Where a [`Reader`]'s records come from
 #[allow(clippy::large_enum_variant)]
-enum Reader<'a> {
-    Uncompressed(tacview::Parser<BufReader<&'a mut File>>),
+enum Source<'a> {
+    Uncompressed(tacview::Parser<BufReader<&'a mut Input>>),
     Compressed(tacview::Parser<zip::read::ZipFile<'a>>),
+    /// Plain or compressed, rewriting decimal commas as we go
+    Normalized(tacview::Parser<DecimalCommas<Box<dyn BufRead + 'a>>>),
 }
 
 impl Iterator for Reader<'_> {
-    type Item = Result<Record, ParseError>;
+    type Item = Result<Record>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self {
-            Self::Uncompressed(u) => u.next(),
-            Self::Compressed(c) => c.next(),
+        let next = match &mut self.source {
+            Source::Uncompressed(u) => u.next(),
+            Source::Compressed(c) => c.next(),
+            Source::Normalized(n) => n.next(),
+        };
+        let rec = match next? {
+            Ok(r) => r,
+            Err(e) => return Some(Err(self.context(e))),
+        };
+
+        self.records += 1;
+        match &rec {
+            Record::Frame(t) => self.frame = Some(*t),
+            Record::Update(up) => self.object = Some(up.id),
+            _ => {}
         }
+        Some(Ok(rec))
     }
 }
 
 impl<'a> Reader<'a> {
-    fn new(name: &Utf8Path, fh: &'a mut File) -> Result<Self> {
-        let r = if name.as_str().ends_with(".zip.acmi") {
+    fn new(fh: &'a mut Input, decimal_comma: bool, id_base: IdBase) -> Result<Self> {
+        let source = if decimal_comma {
+            let lines = DecimalCommas::new(open_lines(fh)?);
+            Source::Normalized(tacview::Parser::new(lines)?)
+        } else if fh.is_zipped()? {
             // No need for BufReader, DEFLATE (ZIP compression) has its own buffer.
-            Reader::Compressed(tacview::Parser::new_compressed(fh)?)
+            // The zip crate's stream reader reads zip64 sizes from the local header,
+            // so entries over 4GB are fine. (See the zip64 test.)
+            Source::Compressed(tacview::Parser::new_compressed(fh)?)
         } else {
-            Reader::Uncompressed(tacview::Parser::new(BufReader::new(fh))?)
+            Source::Uncompressed(tacview::Parser::new(BufReader::new(fh))?)
         };
-        Ok(r)
+        Ok(Self {
+            source,
+            records: 0,
+            frame: None,
+            object: None,
+            id_base,
+        })
+    }
+
+    /// Say where the parse error happened.
+    ///
+    /// The parser doesn't give us the line it choked on,
+    /// so the best we can do is the last things it parsed.
+    fn context(&self, e: ParseError) -> anyhow::Error {
+        let mut at = format!("Couldn't parse record #{}", self.records + 1);
+        if let Some(t) = self.frame {
+            at += &format!(" in frame {t}");
+        }
+        if let Some(id) = self.object {
+            at += &format!(" (after an update to object {})", self.id_base.format(id));
+        }
+        anyhow::Error::from(e).context(at)
+    }
+}
+
+/// Get at the ACMI's text, unzipping it if needed.
+fn open_lines(fh: &mut Input) -> Result<Box<dyn BufRead + '_>> {
+    if fh.is_zipped()? {
+        let acmi = zip::read::read_zipfile_from_stream(fh)?
+            .context("Couldn't find an ACMI in the ZIP file")?;
+        Ok(Box::new(BufReader::new(acmi)))
+    } else {
+        Ok(Box::new(BufReader::new(fh)))
     }
 }
 
+/// ACMI versions the tacview crate can parse
+const SUPPORTED_VERSIONS: &[&str] = &["2.1", "2.2"];
+
+/// Check the ACMI's FileType and FileVersion up front,
+/// since the parser's errors for files it can't handle are pretty opaque.
+fn check_version(name: &Utf8Path, fh: &mut Input, decimal_comma: bool) -> Result<()> {
+    let mut header = String::new();
+    {
+        // They should be the first two lines; don't read a whole file looking for them.
+        let mut lines = open_lines(fh)?.take(4096);
+        for _ in 0..2 {
+            if lines
+                .read_line(&mut header)
+                .context("Couldn't read ACMI header")?
+                == 0
+            {
+                break;
+            }
+        }
+    }
+    fh.rewind()?;
+
+    let mut file_type = None;
+    let mut version = None;
+    for line in header.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim_end();
+        if let Some(t) = line.strip_prefix("FileType=") {
+            file_type = Some(t);
+        } else if let Some(v) = line.strip_prefix("FileVersion=") {
+            version = Some(v.to_owned());
+        }
+    }
+
+    let file_type = match file_type {
+        Some(t) => t,
+        None => bail!("{name} doesn't look like an ACMI (no FileType)"),
+    };
+    let mut version = match version {
+        Some(v) => v,
+        None => bail!("{name} has no FileVersion"),
+    };
+    if decimal_comma {
+        version = version.replace(',', ".");
+    }
+    info!("{name} is {file_type} version {version}");
+
+    if file_type != "text/acmi/tacview" {
+        bail!("ACMI FileType {file_type} not supported (expected text/acmi/tacview)");
+    }
+    if !SUPPORTED_VERSIONS.contains(&version.as_str()) {
+        bail!(
+            "ACMI FileVersion {version} not supported by this tacview parser version \
+             (supported: {})",
+            SUPPORTED_VERSIONS.join(", ")
+        );
+    }
+    Ok(())
+}
+
 struct CountingWriter<W> {
     inner: W,
-    written: u64,
+    written: Rc<Cell<u64>>,
 }
 
 impl<W: Write> Write for CountingWriter<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let written = self.inner.write(buf)?;
-        self.written += written as u64;
+        self.written.set(self.written.get() + written as u64);
         Ok(written)
     }
 
@@ -85,7 +733,16 @@ impl<W: Write> Write for CountingWriter<W> {
 
 impl<W: Write> CountingWriter<W> {
     fn new(inner: W) -> Self {
-        Self { inner, written: 0 }
+        Self {
+            inner,
+            written: Rc::default(),
+        }
+    }
+
+    /// A handle to the byte count that can be read
+    /// while something else (like a tacview::Writer) owns us.
+    fn counter(&self) -> Rc<Cell<u64>> {
+        self.written.clone()
     }
 }
 
@@ -97,22 +754,370 @@ struct LL {
 
 type PropertyMap = FxHashMap<Discriminant<Property>, Property>;
 
+/// How many records the read thread parses (then transforms) at a time
+const READ_BATCH: usize = 1024;
+
 fn run() -> Result<()> {
     let args = Args::parse();
     init_logger(&args);
+    // Held until we're done so the trace is flushed.
+    let _trace = args.trace_file.as_deref().map(trace::init).transpose()?;
+
+    match &args.command {
+        Some(Command::ToHeatmap { cells, acmi }) => {
+            return heatmap::heatmap(acmi, *cells, args.id_base, args.slice(), args.decimal_comma)
+        }
+        Some(Command::Validate {
+            header_only: true,
+            acmi,
+        }) => {
+            return validate::validate_header(acmi, args.id_base, args.slice(), args.decimal_comma)
+        }
+        Some(Command::Validate {
+            header_only: false,
+            acmi,
+        }) => {
+            return validate::validate(
+                acmi,
+                args.max_speed,
+                args.id_base,
+                args.slice(),
+                args.decimal_comma,
+            )
+        }
+        Some(Command::Inspect {
+            header_only,
+            cache,
+            records_per_frame_histogram,
+            acmi,
+        }) => {
+            return inspect::inspect(
+                acmi,
+                *header_only,
+                *cache,
+                *records_per_frame_histogram,
+                args.id_base,
+                args.slice(),
+                args.decimal_comma,
+            )
+        }
+        Some(Command::SplitMissions { out_dir, acmi }) => {
+            return missions::split(acmi, out_dir, args.slice())
+        }
+        Some(Command::Env { acmi }) => {
+            return inspect::env(acmi, args.id_base, args.slice(), args.decimal_comma)
+        }
+        Some(Command::Merge { acmi }) => {
+            return merge::merge(acmi, args.id_base, args.slice(), args.decimal_comma)
+        }
+        Some(Command::ExportIndex {
+            out,
+            polyline_points,
+            acmi,
+        }) => {
+            return replay::export(
+                acmi,
+                out,
+                *polyline_points,
+                args.id_base,
+                args.slice(),
+                args.decimal_comma,
+            )
+        }
+        Some(Command::Watch {
+            interval,
+            remove_source,
+            dir,
+        }) => {
+            let template = OutputTemplate::parse(
+                args.output_template
+                    .as_deref()
+                    .unwrap_or("{dir}/{stem}.compressed.{format}"),
+            )?;
+            let interval = Duration::try_from_secs_f64(*interval)
+                .ok()
+                .filter(|i| !i.is_zero())
+                .context("--interval must be a positive number of seconds")?;
+            let w = watch::Watch {
+                dir,
+                template,
+                interval,
+                remove_source: *remove_source,
+            };
+            return w.run(&args);
+        }
+        Some(Command::ListTransforms) => {
+            transform::list();
+            return Ok(());
+        }
+        Some(Command::Selftest) => return selftest::selftest(),
+        Some(Command::Events { json, acmi }) => {
+            return lifecycle::lifecycle(
+                acmi,
+                *json,
+                args.id_base,
+                args.slice(),
+                args.decimal_comma,
+            )
+        }
+        None => {}
+    }
+
+    let start = Instant::now();
+    let stats = compress_all(&args)?;
+    if let Some(path) = &args.metrics_file {
+        metrics::write(path, &stats, start.elapsed())?;
+    }
+    Ok(())
+}
+
+/// Compress each input ACMI, as the args say to, returning the totals.
+fn compress_all(args: &Args) -> Result<Stats> {
+    let template = args
+        .output_template
+        .as_deref()
+        .map(OutputTemplate::parse)
+        .transpose()?;
+    let index_template = args
+        .emit_index
+        .as_deref()
+        .map(OutputTemplate::parse)
+        .transpose()?;
+    let index_path = |acmi: &Utf8Path| index_template.as_ref().map(|t| t.expand(acmi));
+
+    // Check before compressing anything, instead of having each ACMI overwrite the last.
+    if args.acmi.len() > 1 {
+        for (flag, t) in [
+            ("--output-template", &template),
+            ("--emit-index", &index_template),
+        ] {
+            if t.as_ref().is_some_and(|t| !t.is_per_input()) {
+                bail!(
+                    "{flag} needs a {{stem}} to give each of the {} ACMIs its own output",
+                    args.acmi.len()
+                );
+            }
+        }
+    }
+
+    if let (Some(tar), Some(t)) = (&args.archive, &template) {
+        return archive::compress(tar, t, index_template.as_ref(), args);
+    }
+
+    if template.is_none() && !args.dedup_report && !args.estimate {
+        if args.acmi.len() > 1 {
+            bail!("Compressing multiple ACMIs requires an --output-template");
+        }
+        let acmi = &args.acmi[0];
+        return compress(acmi, io::stdout(), index_path(acmi), args);
+    }
+
+    // Crashed sorties can leave behind empty recordings.
+    // Skip them in batches instead of failing halfway through.
+    let mut empty = 0;
+    let mut compress_one = |acmi: &Utf8Path| -> Result<Stats> {
+        if args.acmi.len() > 1 && is_empty(acmi, args.slice())? {
+            warn!("Skipping {acmi}: it's empty");
+            empty += 1;
+            return Ok(Stats::default());
+        }
+        match &template {
+            Some(t) => compress_to(acmi, &t.expand(acmi), index_path(acmi), args),
+            None if args.estimate => estimate(acmi, args),
+            // --dedup-report
+            None => compress(acmi, io::sink(), None, args)
+                .with_context(|| format!("Couldn't compress {acmi}")),
+        }
+    };
+
+    let mut stats = Stats::default();
+    let mut failures = vec![];
+    for acmi in &args.acmi {
+        match compress_one(acmi) {
+            Ok(s) => stats += s,
+            Err(e) if args.fail_fast => return Err(e),
+            Err(e) => {
+                error!("{e:#}");
+                failures.push((acmi, e));
+            }
+        }
+    }
+    if empty > 0 {
+        warn!("Skipped {empty} empty ACMIs");
+    }
+
+    report_failures(&failures, args.acmi.len())?;
+    Ok(stats)
+}
+
+/// List the ACMIs that failed to compress (if any) and error out.
+fn report_failures<P: AsRef<Utf8Path>>(
+    failures: &[(P, anyhow::Error)],
+    total: usize,
+) -> Result<()> {
+    if !failures.is_empty() {
+        error!("{} of {total} ACMIs failed:", failures.len());
+        for (acmi, e) in failures {
+            error!("  {}: {e:#}", acmi.as_ref());
+        }
+        bail!("{} ACMIs failed", failures.len());
+    }
+    Ok(())
+}
+
+/// Compress the first --estimate-records records into a sink,
+/// and scale what they came to by how much of the input they were.
+fn estimate(acmi: &Utf8Path, args: &Args) -> Result<Stats> {
+    let total = args.slice().open(acmi)?.len();
+    let sample = compress(acmi, io::sink(), None, args)
+        .with_context(|| format!("Couldn't sample {acmi}"))?;
+    let predicted = if sample.original_bytes == 0 {
+        0
+    } else {
+        (sample.compressed_bytes as f64 * total as f64 / sample.original_bytes as f64) as u64
+    };
+    println!(
+        "{acmi}: about {} (from a sample of {} of its {})",
+        ByteSize::b(predicted),
+        ByteSize::b(sample.original_bytes),
+        ByteSize::b(total)
+    );
+    // Nothing was written, so there's nothing to count in --metrics-file.
+    Ok(Stats::default())
+}
+
+/// Is the part of the file we'd read (see --input-offset and --input-length) empty?
+fn is_empty(acmi: &Utf8Path, slice: Slice) -> Result<bool> {
+    let fh = slice
+        .open(acmi)
+        .with_context(|| format!("Couldn't open {acmi}"))?;
+    Ok(fh.len() == 0)
+}
+
+/// Compress the ACMI to the given file, creating its directory if needed.
+///
+/// We write to `<output>.partial` and rename it into place once we're done,
+/// so a failed run never leaves a truncated output behind.
+fn compress_to(
+    acmi: &Utf8Path,
+    output: &Utf8Path,
+    index: Option<Utf8PathBuf>,
+    args: &Args,
+) -> Result<Stats> {
+    if let Some(dir) = output.parent().filter(|d| !d.as_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Couldn't create {dir}"))?;
+    }
+    if same_file(acmi, output)? {
+        bail!("Output template would overwrite {acmi} with itself ({output})");
+    }
+
+    let partial = Utf8PathBuf::from(format!("{output}.partial"));
+    let fh = File::create(&partial).with_context(|| format!("Couldn't create {partial}"))?;
+
+    info!("Compressing {acmi} to {output}");
+    match compress(acmi, fh, index, args) {
+        Ok(stats) => {
+            fs::rename(&partial, output)
+                .with_context(|| format!("Couldn't move {partial} to {output}"))?;
+            Ok(stats)
+        }
+        Err(e) => {
+            if let Err(rm) = fs::remove_file(&partial) {
+                warn!("Couldn't remove {partial}: {rm}");
+            }
+            Err(e.context(format!("Couldn't compress {acmi}")))
+        }
+    }
+}
 
-    if args.acmi == "-" {
+/// Are these the same file, even if they're spelled differently
+/// (like `./foo.acmi` and `foo.acmi`, or through a symlink)?
+fn same_file(acmi: &Utf8Path, output: &Utf8Path) -> Result<bool> {
+    let acmi = fs::canonicalize(acmi).with_context(|| format!("Couldn't open {acmi}"))?;
+    // If the output isn't there yet, it can't be the input.
+    Ok(fs::canonicalize(output).is_ok_and(|o| o == acmi))
+}
+
+/// A scratch ACMI in the temp directory, named for what it's for and this process
+/// (so concurrent runs don't trample each other)
+fn temp_acmi(purpose: &str) -> Result<Utf8PathBuf> {
+    let path = env::temp_dir().join(format!("acmi-compress-{purpose}-{}.acmi", process::id()));
+    Utf8PathBuf::from_path_buf(path)
+        .map_err(|p| anyhow::anyhow!("Temp directory {} isn't UTF-8", p.display()))
+}
+
+fn compress<W: Write + Send>(
+    acmi: &Utf8Path,
+    output: W,
+    index: Option<Utf8PathBuf>,
+    args: &Args,
+) -> Result<Stats> {
+    if acmi == "-" {
         bail!("Reading from stdin currently unsupported (can't seek that!)");
     }
+    if is_empty(acmi, args.slice())? {
+        bail!("{acmi} is empty");
+    }
+    // Checked here so that watch (and anything else that compresses) checks it too.
+    if !(args.time_scale.is_finite() && args.time_scale > 0.0) {
+        bail!("--time-scale must be a positive number");
+    }
+
+    let only = args
+        .only
+        .iter()
+        .map(|id| args.id_base.parse(id))
+        .collect::<Result<Vec<_>>>()?;
+
+    let span = info_span!("compress", %acmi);
+    let _compress = span.enter();
 
-    let mut fh = File::open(&args.acmi).context("Couldn't open ACMI")?;
+    let mut fh = args.slice().open(acmi)?;
 
-    let mut reader = Reader::new(&args.acmi, &mut fh)?;
+    check_version(acmi, &mut fh, args.decimal_comma)?;
+
+    let mut reader = Reader::new(&mut fh, args.decimal_comma, args.id_base)?;
 
     let reference_ll = parse_original_ll(&mut reader)?;
     debug!("Original reference lat/lon: {reference_ll:?}");
 
-    let min_ll = find_min_ll(reader)?;
+    let mut parents = args.include_children.then(FxHashMap::default);
+    let mut ids = args.sample_objects.map(|_| FxHashSet::default());
+    // Without --lenient, we check --max-objects on this first pass,
+    // before writing anything.
+    let mut limit = args
+        .max_objects
+        .filter(|_| !args.lenient)
+        .map(|max| ObjectLimit::new(max, args.id_base));
+    // With --estimate, both passes only read the sample.
+    let records = if args.estimate {
+        args.estimate_records
+    } else {
+        usize::MAX
+    };
+    let scanned = info_span!("scan").in_scope(|| {
+        find_min_ll(
+            reader.take(records),
+            parents.as_mut(),
+            ids.as_mut(),
+            limit.as_mut(),
+        )
+    });
+    let min_ll = match scanned {
+        Ok(ll) => ll,
+        Err(e) => {
+            // A common cause of garbage mid-file: another recording appended to this one.
+            fh.rewind()?;
+            let missions = missions::count(&mut fh)?;
+            if missions > 1 {
+                bail!(
+                    "{acmi} holds {missions} ACMIs appended together; \
+                     split them with the split-missions subcommand first"
+                );
+            }
+            return Err(e);
+        }
+    };
     debug!("Min lat/lon: {min_ll:?}");
     let new_reference_ll = LL {
         lat: (reference_ll.lat + min_ll.lat).floor(),
@@ -123,56 +1128,221 @@ fn run() -> Result<()> {
     let original_size = fh.stream_position()?;
     fh.rewind()?;
 
+    let mut filter = Filter::default();
+    if !only.is_empty() {
+        filter = filter.only(&only, parents.as_ref());
+    }
+    if let (Some(n), Some(ids)) = (args.sample_objects, &ids) {
+        filter = filter.only(&filter::sample(ids, n, args.seed()), None);
+    }
+    if only.contains(&0) {
+        warn!("Object 0 is the global object, which --only always keeps (see --drop-global)");
+    }
+    if args.drop_global {
+        filter = filter.drop_global();
+    } else if args.keep_global {
+        filter = filter.only_global();
+    }
+    if args.drop.contains(&DropKind::Event) {
+        filter = filter.drop_events();
+    } else if !args.event_type.is_empty() {
+        filter = filter.only_events(&args.event_type);
+    }
+
+    // Coordinates are moved to the new reference point first,
+    // so everything after works with what we'll write.
+    let mut pipeline = Pipeline::default();
+    pipeline.push(Box::new(Reposition {
+        from: reference_ll,
+        to: new_reference_ll,
+    }));
+    if let (Some(max), true) = (args.max_objects, args.lenient) {
+        pipeline.push(Box::new(ObjectLimit::new(max, args.id_base)));
+    }
+    pipeline.push(Box::new(filter));
+    if let Some(csv) = &args.rename {
+        pipeline.push(Box::new(rename::Rename::load(csv, args.id_base)?));
+    }
+    if args.repair {
+        pipeline.push(Box::new(Validator::new(
+            new_reference_ll,
+            args.max_speed,
+            args.id_base,
+        )));
+    }
+    if args.precision.is_some() || args.precision_spec.is_some() {
+        let mut precision = args
+            .precision
+            .map_or_else(Precision::default, Precision::uniform);
+        if let Some(spec) = &args.precision_spec {
+            precision = precision.with_spec(spec)?;
+        }
+        pipeline.push(Box::new(precision));
+    }
+    if args.drop_static {
+        pipeline.push(Box::new(ParkedFilter::new(
+            args.static_threshold,
+            args.static_updates,
+            new_reference_ll.lat,
+        )));
+    }
+    if let Some(epsilon) = args.dedup_epsilon {
+        pipeline.push(Box::new(JitterFilter::new(
+            epsilon,
+            args.dedup_max_hold,
+            new_reference_ll.lat,
+        )));
+    }
+    if args.time_scale != 1.0 {
+        pipeline.push(Box::new(TimeScale {
+            factor: args.time_scale,
+        }));
+    }
+    if args.close_dangling {
+        pipeline.push(Box::<CloseDangling>::default());
+    }
+    if let Some(pipe) = &args.pipe {
+        let ctx = StageContext {
+            reference: new_reference_ll,
+            max_speed: args.max_speed,
+            dedup_max_hold: args.dedup_max_hold,
+            static_updates: args.static_updates,
+            id_base: args.id_base,
+        };
+        pipeline.extend(transform::parse_pipe(pipe, &ctx)?);
+    }
+
     let (tx, rx) = bounded(1024);
 
+    // Spans don't follow us into other threads, so give them their parent explicitly.
+    let write_span = info_span!(parent: &span, "write", records = tracing::field::Empty);
+    let read_span = info_span!(parent: &span, "read", records = tracing::field::Empty);
     std::thread::scope(|s| {
-        let write_thread =
-            s.spawn(move || writer_thread(rx, &reference_ll, &new_reference_ll, original_size));
+        let write_thread = s.spawn(move || {
+            let _write = write_span.entered();
+            writer_thread(
+                acmi,
+                rx,
+                output,
+                &new_reference_ll,
+                original_size,
+                index.as_deref(),
+                args,
+            )
+        });
 
         let read_thread = s.spawn(move || {
-            let reader = Reader::new(&args.acmi, &mut fh)?;
-            for rec in reader {
-                if tx.send(rec?).is_err() {
+            let read_span = read_span.entered();
+            let mut reader = Reader::new(&mut fh, args.decimal_comma, args.id_base)?.take(records);
+            // Measure what came in before the pipeline drops anything.
+            let mut originals = (args.size_breakdown || args.dedup_report)
+                .then(Originals::new)
+                .transpose()?;
+            // Parse and transform records in batches,
+            // so that tracing can time each without a span per record.
+            let mut batch = Vec::with_capacity(READ_BATCH);
+            let mut out = vec![];
+            let mut read = 0u64;
+            'read: loop {
+                trace_span!("parse").in_scope(|| {
+                    for rec in reader.by_ref().take(READ_BATCH) {
+                        let rec = rec?;
+                        if let Some(o) = &mut originals {
+                            o.measure(&rec)?;
+                        }
+                        batch.push(rec);
+                    }
+                    anyhow::Ok(())
+                })?;
+                if batch.is_empty() {
+                    break;
+                }
+                read += batch.len() as u64;
+                trace_span!("transform").in_scope(|| {
+                    for rec in batch.drain(..) {
+                        pipeline.run(rec, &mut out);
+                    }
+                });
+                for rec in out.drain(..) {
+                    if tx.send(rec).is_err() {
+                        break 'read;
+                    }
+                }
+            }
+            trace_span!("flush").in_scope(|| pipeline.flush(&mut out));
+            for rec in out {
+                if tx.send(rec).is_err() {
                     break;
                 }
             }
-            anyhow::Ok(())
+            read_span.record("records", read);
+            anyhow::Ok(originals.map(|o| o.sizes()))
         });
 
-        write_thread.join().expect("Couldn't join writer thread")?;
-        read_thread.join().expect("Couldn't join reader thread")?;
-        anyhow::Ok(())
-    })?;
+        let (stats, breakdown) = write_thread.join().expect("Couldn't join writer thread")?;
+        let originals = read_thread.join().expect("Couldn't join reader thread")?;
+        if let (Some(mut b), Some(sizes)) = (breakdown, originals) {
+            b.originals(sizes);
+            report_breakdown(acmi, &b, args);
+        }
+        anyhow::Ok(stats)
+    })
+}
 
-    Ok(())
+/// Log --size-breakdown, and print --dedup-report.
+fn report_breakdown(acmi: &Utf8Path, breakdown: &SizeBreakdown, args: &Args) {
+    if args.size_breakdown {
+        breakdown.log();
+    }
+    if args.dedup_report {
+        println!(
+            "{acmi}: dedup would remove {:.1}% of update bytes",
+            breakdown.savings(Category::Update) * 100.0
+        );
+    }
 }
 
-fn writer_thread(
+fn writer_thread<W: Write>(
+    acmi: &Utf8Path,
     record_rx: Receiver<Record>,
+    output: W,
     reference_ll: &LL,
-    new_reference_ll: &LL,
     original_size: u64,
-) -> Result<()> {
-    let mut w = tacview::Writer::new(CountingWriter::new(BufWriter::new(io::stdout().lock())))?;
+    index_path: Option<&Utf8Path>,
+    args: &Args,
+) -> Result<(Stats, Option<SizeBreakdown>)> {
+    let output = CountingWriter::new(BufWriter::new(output));
+    let written = output.counter();
+    let mut w = tacview::Writer::new(output)?;
 
     let mut this_frame = 0f64;
-    let mut active_entities: FxHashMap<u64, PropertyMap> = FxHashMap::default();
+    let mut frames = 0u64;
+    let mut active_entities = ObjectStates::new(args.state_cap);
 
     // Dumb experiment
     let mut total_coords = 0u64;
     let mut total_props = 0u64;
 
+    let mut index = index_path.map(|_| FrameIndex::default());
+    let mut breakdown = (args.size_breakdown || args.dedup_report).then(SizeBreakdown::default);
+
+    let mut records = 0u64;
+
     info!("Rewriting all records");
     while let Ok(rec) = record_rx.recv() {
+        records += 1;
+        let category = breakdown.is_some().then(|| Category::of(&rec));
+        let written_before = written.get();
+
         match rec {
             // Pass global properties through, except lat/lon.
             // Change those to the new one!
             Record::GlobalProperty(gp) => match gp {
                 GlobalProperty::ReferenceLatitude(_) => {
-                    w.write(GlobalProperty::ReferenceLatitude(new_reference_ll.lat))?;
+                    w.write(GlobalProperty::ReferenceLatitude(reference_ll.lat))?;
                 }
                 GlobalProperty::ReferenceLongitude(_) => {
-                    w.write(GlobalProperty::ReferenceLongitude(new_reference_ll.lon))?;
+                    w.write(GlobalProperty::ReferenceLongitude(reference_ll.lon))?;
                 }
                 not_ll => w.write(not_ll)?,
             },
@@ -182,7 +1352,11 @@ fn writer_thread(
 
             // Pass frame timestamps through only when they're new.
             Record::Frame(ts) => {
+                frames += 1;
                 if ts != this_frame {
+                    if let Some(i) = &mut index {
+                        i.push(ts, written.get());
+                    }
                     w.write(Record::Frame(ts))?;
                     this_frame = ts;
                 } else {
@@ -191,10 +1365,8 @@ fn writer_thread(
             }
 
             Record::Update(mut up) => {
-                // Fix up coords.
-                for prop in &mut up.props {
-                    if let Property::T(c) = prop {
-                        offset_coords(c, &reference_ll, &new_reference_ll);
+                for prop in &up.props {
+                    if let Property::T(_) = prop {
                         total_coords += 1;
                     }
                     total_props += 1;
@@ -202,25 +1374,27 @@ fn writer_thread(
 
                 let props = props_map(up.props);
 
-                use std::collections::hash_map::Entry;
-
-                match active_entities.entry(up.id) {
-                    Entry::Vacant(v) => {
+                match active_entities.get_mut(up.id) {
+                    None => {
                         // Back into a list you go
                         // (change tacview-rs to store maps?)
+                        let id = up.id;
                         up.props = props.values().cloned().collect();
+                        if args.normalize_property_order {
+                            order::normalize(&mut up.props);
+                        }
                         w.write(up)?;
 
-                        v.insert(props);
+                        active_entities.insert(id, props);
                     }
-                    Entry::Occupied(mut o) => {
+                    Some(prev_props) => {
                         let mut changed_props = PropertyMap::default();
 
                         // For each property in the new update,
                         for (prop_type, prop) in props {
                             // If we were already tracking that property and it changed,
                             // note that.
-                            if let Some(prev) = o.get().get(&prop_type) {
+                            if let Some(prev) = prev_props.get(&prop_type) {
                                 // Coords are a speical case:
                                 // Like properties, individiual entries in them
                                 // can be left blank if they haven't changed.
@@ -248,13 +1422,14 @@ fn writer_thread(
 
                         if !changed_props.is_empty() {
                             // We only need to record properties that changed:
-                            w.write(Update {
-                                id: up.id,
-                                props: changed_props.values().cloned().collect(),
-                            })?;
+                            let mut props: Vec<_> = changed_props.values().cloned().collect();
+                            if args.normalize_property_order {
+                                order::normalize(&mut props);
+                            }
+                            w.write(Update { id: up.id, props })?;
 
                             // And merge them back into our record
-                            o.get_mut().extend(changed_props);
+                            prev_props.extend(changed_props);
                         } else {
                             // trace!("No properties changed for {:x} at {}", up.id, this_frame);
                         }
@@ -264,18 +1439,33 @@ fn writer_thread(
 
             // Pass removals through if they're something we're tracking.
             Record::Remove(id) => {
-                if active_entities.remove(&id).is_some() {
+                if active_entities.remove(id) {
                     w.write(Record::Remove(id))?;
                 } else {
                     trace!("Skipping redundant remove for {id:x}");
                 }
             }
         };
+
+        if let (Some(b), Some(c)) = (&mut breakdown, category) {
+            b.compressed(c, written.get() - written_before);
+        }
+    }
+
+    if active_entities.evictions > 0 {
+        info!(
+            "Evicted {} object states to stay under --state-cap",
+            active_entities.evictions
+        );
     }
 
     let mut w = w.into_inner();
     w.flush()?;
-    let compressed_size = w.written;
+    let compressed_size = written.get();
+
+    if let (Some(i), Some(path)) = (&index, index_path) {
+        i.write(path)?;
+    }
 
     info!(
         "Compressed {} ACMI to {} ({:.1}%)",
@@ -283,14 +1473,22 @@ fn writer_thread(
         ByteSize::b(compressed_size),
         compressed_size as f64 / original_size as f64 * 100.0
     );
+    if frames == 0 {
+        warn!("{acmi} has no frames, just a header");
+    }
     debug!(
         "{}/{} coords ({:.1}%)",
         total_coords,
         total_props,
         total_coords as f64 / total_props as f64 * 100.0
     );
-
-    Ok(())
+    Span::current().record("records", records);
+    let stats = Stats {
+        original_bytes: original_size,
+        compressed_bytes: compressed_size,
+        records,
+    };
+    Ok((stats, breakdown))
 }
 
 fn offset_coords(c: &mut Coords, old_ref: &LL, new_ref: &LL) {
@@ -335,14 +1533,43 @@ fn parse_original_ll(reader: &mut Reader) -> Result<LL> {
     Ok(reference_ll)
 }
 
-fn find_min_ll(records: Reader) -> Result<LL> {
+/// Find the minimum lat/lon of all objects,
+/// and if `parents` are given, each child object's parent.
+/// If `ids` are given, collect every object's ID.
+/// If a `limit` is given, bail if there are ever too many live objects.
+fn find_min_ll(
+    records: impl Iterator<Item = Result<Record>>,
+    mut parents: Option<&mut FxHashMap<u64, u64>>,
+    mut ids: Option<&mut FxHashSet<u64>>,
+    mut limit: Option<&mut ObjectLimit>,
+) -> Result<LL> {
     info!("Reading all records to find the minimum lat/lon");
 
     let mut new_ref_lat = None;
     let mut new_ref_lon = None;
 
     for rec in records {
-        if let Record::Update(Update { props, .. }) = rec? {
+        let rec = rec?;
+        if let Some(limit) = &mut limit {
+            if !limit.admit(&rec) {
+                bail!(
+                    "{} (pass --lenient to drop the extras instead)",
+                    limit.describe()
+                );
+            }
+        }
+        if let Record::Update(Update { id, props }) = rec {
+            if let Some(ids) = &mut ids {
+                ids.insert(id);
+            }
+            if let Some(parents) = &mut parents {
+                for prop in &props {
+                    if let Property::Parent(parent) = prop {
+                        parents.insert(id, *parent);
+                    }
+                }
+            }
+
             if let Some(coords) = props.iter().find(|p| matches!(p, Property::T(_))) {
                 let coords = match coords {
                     Property::T(t) => t,
@@ -374,7 +1601,7 @@ fn find_min_ll(records: Reader) -> Result<LL> {
 fn main() {
     run().unwrap_or_else(|e| {
         log::error!("{:?}", e);
-        std::process::exit(1);
+        process::exit(1);
     });
 }
 
@@ -385,11 +1612,13 @@ fn init_logger(args: &Args) {
     builder.set_thread_level(LevelFilter::Off);
     builder.set_time_level(LevelFilter::Off);
 
-    let level = match args.verbose {
-        0 => LevelFilter::Warn,
-        1 => LevelFilter::Info,
-        2 => LevelFilter::Debug,
-        _ => LevelFilter::Trace,
+    let level = match (args.quiet, args.verbose) {
+        (0, 0) => LevelFilter::Warn,
+        (0, 1) => LevelFilter::Info,
+        (0, 2) => LevelFilter::Debug,
+        (0, _) => LevelFilter::Trace,
+        (1, _) => LevelFilter::Error,
+        (_, _) => LevelFilter::Off,
     };
 
     if level == LevelFilter::Trace {
@@ -402,7 +1631,7 @@ fn init_logger(args: &Args) {
     let color = match args.color {
         Color::Always => ColorChoice::AlwaysAnsi,
         Color::Auto => {
-            if atty::is(atty::Stream::Stderr) {
+            if atty::is(atty::Stream::Stderr) && !headless() {
                 ColorChoice::Auto
             } else {
                 ColorChoice::Never
@@ -416,3 +1645,12 @@ fn init_logger(args: &Args) {
         .context("Couldn't init logger")
         .unwrap()
 }
+
+/// Does the environment say not to color, even if stderr looks like a terminal?
+///
+/// Containers and CI runners often give us a pty whose log viewer
+/// can't handle escape codes.
+fn headless() -> bool {
+    let set = |var| env::var_os(var).is_some_and(|v| !v.is_empty());
+    set("NO_COLOR") || set("CI") || env::var_os("TERM").is_some_and(|t| t == "dumb")
+}
@@ -0,0 +1,139 @@
+use std::io::{self, prelude::*, BufWriter};
+
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use log::*;
+use rustc_hash::FxHashSet;
+use tacview::record::{GlobalProperty, Property, Record};
+
+use crate::{check_version, filter::IdBase, input::Slice, Reader, LL};
+
+/// Join recordings end to end into one ACMI on stdout,
+/// reading them one at a time so memory stays proportional to the live objects.
+///
+/// The header (and reference point) comes from the first file;
+/// global properties later in each file are kept.
+/// Each later file is shifted in time so that its first frame lands on
+/// the previous file's last one, and its coordinates are moved to the first file's
+/// reference point.
+///
+/// Objects still around at the end of one file and updated in the next one's
+/// first frame carry on as the same object. Objects that aren't are removed
+/// right after that first frame, since Tacview doesn't write removals for
+/// objects that were around when it stopped recording.
+pub fn merge(
+    acmis: &[Utf8PathBuf],
+    id_base: IdBase,
+    slice: Slice,
+    decimal_comma: bool,
+) -> Result<()> {
+    let mut w = tacview::Writer::new(BufWriter::new(io::stdout().lock()))?;
+
+    let mut reference: Option<LL> = None;
+    let mut live: FxHashSet<u64> = FxHashSet::default();
+    // The last frame time we wrote
+    let mut last_time: Option<f64> = None;
+
+    for (i, acmi) in acmis.iter().enumerate() {
+        let mut fh = slice.open(acmi)?;
+        check_version(acmi, &mut fh, decimal_comma)?;
+        info!("Merging {acmi}");
+
+        // This file's reference point, and how far it is from the output's
+        let mut here = LL::default();
+        let mut shift = LL::default();
+        // Time offset for this file, set at its first frame
+        let mut offset: Option<f64> = None;
+        let mut frames = 0u64;
+        // Objects carried over from the last file that haven't shown up in this one yet
+        let mut unconfirmed = live.clone();
+
+        let mut in_header = true;
+        for rec in Reader::new(&mut fh, decimal_comma, id_base)? {
+            let rec = rec.with_context(|| format!("Couldn't merge {acmi}"))?;
+
+            if let Record::GlobalProperty(gp) = rec {
+                if in_header {
+                    match &gp {
+                        GlobalProperty::ReferenceLongitude(lon) => here.lon = *lon,
+                        GlobalProperty::ReferenceLatitude(lat) => here.lat = *lat,
+                        _ => {}
+                    }
+                }
+                // Only the first file's header makes it into the output,
+                // but every file's global properties after that (Comments...) do.
+                if i == 0 || !in_header {
+                    w.write(gp)?;
+                }
+                continue;
+            }
+            if in_header {
+                in_header = false;
+                let to = *reference.get_or_insert(here);
+                shift = LL {
+                    lat: here.lat - to.lat,
+                    lon: here.lon - to.lon,
+                };
+            }
+
+            match rec {
+                Record::Frame(t) => {
+                    frames += 1;
+                    // The first frame is over; whatever didn't show up in it is gone.
+                    if frames == 2 {
+                        remove_all(&mut unconfirmed, &mut live, &mut w)?;
+                    }
+                    let offset = *offset.get_or_insert_with(|| last_time.map_or(0.0, |l| l - t));
+                    let t = t + offset;
+                    if last_time != Some(t) {
+                        w.write(Record::Frame(t))?;
+                        last_time = Some(t);
+                    }
+                }
+                Record::Update(mut up) => {
+                    live.insert(up.id);
+                    unconfirmed.remove(&up.id);
+                    for prop in &mut up.props {
+                        if let Property::T(c) = prop {
+                            if let Some(lat) = &mut c.latitude {
+                                *lat += shift.lat;
+                            }
+                            if let Some(lon) = &mut c.longitude {
+                                *lon += shift.lon;
+                            }
+                        }
+                    }
+                    w.write(up)?;
+                }
+                Record::Remove(id) => {
+                    live.remove(&id);
+                    unconfirmed.remove(&id);
+                    w.write(Record::Remove(id))?;
+                }
+                Record::Event(e) => w.write(Record::Event(e))?,
+                Record::GlobalProperty(_) => unreachable!(),
+            }
+        }
+        // A file with just one frame (or none)
+        remove_all(&mut unconfirmed, &mut live, &mut w)?;
+        debug!(
+            "{acmi}: {frames} frames, {} objects live at the end",
+            live.len()
+        );
+    }
+    w.into_inner().flush()?;
+    Ok(())
+}
+
+/// Remove `gone` objects, both from the output and from the `live` ones.
+fn remove_all<W: Write>(
+    gone: &mut FxHashSet<u64>,
+    live: &mut FxHashSet<u64>,
+    w: &mut tacview::Writer<W>,
+) -> Result<()> {
+    for id in gone.drain() {
+        live.remove(&id);
+        w.write(Record::Remove(id))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::{fs, io, time::UNIX_EPOCH};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use log::*;
+
+use crate::input::Slice;
+
+/// Identifies (and versions) header cache files
+const MAGIC: &str = "acmi-meta 1";
+
+/// Caches an ACMI's printed header in `<acmi>.acmi-meta`,
+/// so that inspecting the same big recording again needn't reparse it.
+///
+/// The cache is keyed on the ACMI's size and modification time
+/// (and which slice of it we read, and whether we rewrote decimal commas),
+/// and is ignored if they don't match.
+pub struct HeaderCache {
+    path: Utf8PathBuf,
+    key: String,
+}
+
+impl HeaderCache {
+    pub fn new(acmi: &Utf8Path, slice: Slice, decimal_comma: bool) -> io::Result<Self> {
+        let md = fs::metadata(acmi)?;
+        let mtime = md
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Ok(Self {
+            path: Utf8PathBuf::from(format!("{acmi}.acmi-meta")),
+            key: format!(
+                "{MAGIC} {} {mtime} {} {:?} {decimal_comma}",
+                md.len(),
+                slice.offset,
+                slice.length
+            ),
+        })
+    }
+
+    /// The cached header, if there is an up-to-date one.
+    pub fn load(&self) -> Option<String> {
+        let cached = fs::read_to_string(&self.path).ok()?;
+        let (key, header) = cached.split_once('\n')?;
+        if key == self.key {
+            debug!("Using cached header from {}", self.path);
+            Some(header.to_owned())
+        } else {
+            debug!("{} is stale; ignoring it", self.path);
+            None
+        }
+    }
+
+    /// Save the header for next time.
+    ///
+    /// The cache is just an optimization, so failing to write it is only a warning.
+    pub fn store(&self, header: &str) {
+        // Write and rename so that a concurrent reader never sees half a cache.
+        let tmp = self.path.with_extension("acmi-meta.tmp");
+        let result = fs::write(&tmp, format!("{}\n{header}", self.key))
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        match result {
+            Ok(()) => debug!("Cached header in {}", self.path),
+            Err(e) => warn!("Couldn't cache header in {}: {e}", self.path),
+        }
+    }
+}
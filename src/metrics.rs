@@ -0,0 +1,68 @@
+use std::{
+    fs::{self, File},
+    io::{prelude::*, BufWriter},
+    ops::AddAssign,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use log::*;
+
+/// Totals from compressing one or more ACMIs
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stats {
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+    /// Records we got from the reader (after filtering)
+    pub records: u64,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Self) {
+        self.original_bytes += other.original_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+        self.records += other.records;
+    }
+}
+
+/// Write the stats to `path` as Prometheus metrics,
+/// in the text format node_exporter's textfile collector reads.
+///
+/// We write to a temporary file next to `path`, then rename it into place,
+/// so the collector never sees a half-written file.
+pub fn write(path: &Utf8Path, stats: &Stats, duration: Duration) -> Result<()> {
+    let tmp = path.with_extension("prom.tmp");
+    {
+        let fh = File::create(&tmp).with_context(|| format!("Couldn't create {tmp}"))?;
+        let mut w = BufWriter::new(fh);
+        let metrics = [
+            (
+                "original_bytes",
+                "Size of the ACMIs read",
+                stats.original_bytes as f64,
+            ),
+            (
+                "compressed_bytes",
+                "Size of the ACMIs written",
+                stats.compressed_bytes as f64,
+            ),
+            ("records_total", "Records compressed", stats.records as f64),
+            (
+                "duration_seconds",
+                "How long the run took",
+                duration.as_secs_f64(),
+            ),
+        ];
+        for (name, help, value) in metrics {
+            writeln!(w, "# HELP acmi_compressor_{name} {help}")?;
+            writeln!(w, "# TYPE acmi_compressor_{name} gauge")?;
+            writeln!(w, "acmi_compressor_{name} {value}")?;
+        }
+        w.flush()
+            .with_context(|| format!("Couldn't write metrics to {tmp}"))?;
+    }
+    fs::rename(&tmp, path).with_context(|| format!("Couldn't move {tmp} to {path}"))?;
+    debug!("Wrote metrics to {path}");
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::{
+    fs::{self, File},
+    io::{prelude::*, BufWriter},
+};
+
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path;
+use log::*;
+
+use crate::{
+    input::{Input, Slice},
+    open_lines,
+    template::split_extension,
+};
+
+/// Does this line start a new ACMI (and the mission recorded in it)?
+fn is_header(line: &[u8]) -> bool {
+    let line = line.strip_prefix("\u{feff}".as_bytes()).unwrap_or(line);
+    line.starts_with(b"FileType=")
+}
+
+/// Count how many ACMIs (each with its own header) were appended into this one.
+pub fn count(fh: &mut Input) -> Result<usize> {
+    let mut missions = 0;
+    {
+        let mut lines = open_lines(fh)?;
+        let mut line = vec![];
+        loop {
+            line.clear();
+            if lines.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            if is_header(&line) {
+                missions += 1;
+            }
+        }
+    }
+    fh.rewind()?;
+    Ok(missions)
+}
+
+/// Split an ACMI with several missions appended into it
+/// into `{out_dir}/{stem}.1.acmi`, `{stem}.2.acmi`, and so on.
+pub fn split(acmi: &Utf8Path, out_dir: &Utf8Path, slice: Slice) -> Result<()> {
+    let mut fh = slice.open(acmi)?;
+    fs::create_dir_all(out_dir).with_context(|| format!("Couldn't create {out_dir}"))?;
+    let (stem, _) = split_extension(acmi.file_name().unwrap_or_default());
+
+    let mut lines = open_lines(&mut fh)?;
+    let mut line = vec![];
+    let mut missions = 0;
+    let mut output: Option<BufWriter<File>> = None;
+    loop {
+        line.clear();
+        if lines.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if is_header(&line) {
+            if let Some(mut o) = output.take() {
+                o.flush()?;
+            }
+            missions += 1;
+            let path = out_dir.join(format!("{stem}.{missions}.acmi"));
+            info!("Writing mission {missions} to {path}");
+            let fh = File::create(&path).with_context(|| format!("Couldn't create {path}"))?;
+            output = Some(BufWriter::new(fh));
+        }
+        match &mut output {
+            Some(o) => o.write_all(&line)?,
+            None => bail!("{acmi} doesn't start with an ACMI header (FileType=...)"),
+        }
+    }
+    if let Some(mut o) = output {
+        o.flush()?;
+    }
+
+    info!("Split {acmi} into {missions} missions");
+    Ok(())
+}
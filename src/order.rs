@@ -0,0 +1,69 @@
+use std::cmp::Ordering;
+
+use tacview::record::Property;
+
+/// Sort an update's properties into a fixed order, so that exporters
+/// that shuffle them between updates don't make otherwise-identical lines differ.
+///
+/// The order is: `T`, then what the object is (`Name`, `Type`, `Parent`, `Next`),
+/// who's flying it (`CallSign`, `Registration`, `Squawk`, `ICAO24`, `Pilot`,
+/// `Group`, `Country`, `Coalition`), how to draw it (`Color`, `Shape`,
+/// `Debug`, `Label`), targeting (`FocusedTarget`, `LockedTarget`),
+/// status (`Importance`, `Slot`, `Disabled`, `Visible`, `Health`),
+/// size (`Length`, `Width`, `Height`, `Radius`), flight data (`IAS`, `CAS`,
+/// `TAS`, `Mach`, `AOA`, `AOS`, `AGL`, `HDG`, `HDM`, `Throttle`),
+/// and finally properties the tacview crate doesn't know, by name.
+pub fn normalize(props: &mut [Property]) {
+    props.sort_by(compare);
+}
+
+fn compare(a: &Property, b: &Property) -> Ordering {
+    match (a, b) {
+        (Property::Unknown(a, _), Property::Unknown(b, _)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn rank(prop: &Property) -> u8 {
+    match prop {
+        Property::T(_) => 0,
+        Property::Name(_) => 1,
+        Property::Type(_) => 2,
+        Property::Parent(_) => 3,
+        Property::Next(_) => 4,
+        Property::CallSign(_) => 5,
+        Property::Registration(_) => 6,
+        Property::Squawk(_) => 7,
+        Property::ICAO24(_) => 8,
+        Property::Pilot(_) => 9,
+        Property::Group(_) => 10,
+        Property::Country(_) => 11,
+        Property::Coalition(_) => 12,
+        Property::Color(_) => 13,
+        Property::Shape(_) => 14,
+        Property::Debug(_) => 15,
+        Property::Label(_) => 16,
+        Property::FocusedTarget(_) => 17,
+        Property::LockedTarget(_) => 18,
+        Property::Importance(_) => 19,
+        Property::Slot(_) => 20,
+        Property::Disabled(_) => 21,
+        Property::Visible(_) => 22,
+        Property::Health(_) => 23,
+        Property::Length(_) => 24,
+        Property::Width(_) => 25,
+        Property::Height(_) => 26,
+        Property::Radius(_) => 27,
+        Property::IAS(_) => 28,
+        Property::CAS(_) => 29,
+        Property::TAS(_) => 30,
+        Property::Mach(_) => 31,
+        Property::AOA(_) => 32,
+        Property::AOS(_) => 33,
+        Property::AGL(_) => 34,
+        Property::HDG(_) => 35,
+        Property::HDM(_) => 36,
+        Property::Throttle(_) => 37,
+        Property::Unknown(..) => 38,
+    }
+}
@@ -0,0 +1,312 @@
+use bytesize::ByteSize;
+use log::*;
+use rustc_hash::FxHashMap;
+use tacview::record::{Coords, Property, Record, Update};
+
+use crate::{coords, transform::RecordTransform};
+
+/// Meters per degree of latitude (or longitude at the equator)
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Stops forwarding updates for objects that never move,
+/// like airfield statics and parked aircraft.
+///
+/// Once an object has had `updates` updates all within `threshold` meters
+/// of where it started, we consider it parked and drop the rest of its positions
+/// until it moves (if it ever does). Everything else about it is kept:
+/// its add and removal, and any other properties (Health, Visible...) that change.
+pub struct ParkedFilter {
+    threshold: f64,
+    updates: u32,
+    /// Reference latitude, used to scale longitudes to meters
+    reference_lat: f64,
+    tracks: FxHashMap<u64, Track>,
+    pub parked: u64,
+}
+
+enum Track {
+    Watching {
+        first: Option<Position>,
+        current: Coords,
+        updates: u32,
+    },
+    Parked {
+        first: Position,
+        current: Coords,
+    },
+    Moving,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Position {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    alt: Option<f64>,
+}
+
+impl Position {
+    fn of(c: &Coords) -> Self {
+        Self {
+            lat: c.latitude,
+            lon: c.longitude,
+            alt: c.altitude,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.lat.is_some() && self.lon.is_some()
+    }
+}
+
+impl ParkedFilter {
+    pub fn new(threshold: f64, updates: u32, reference_lat: f64) -> Self {
+        Self {
+            threshold,
+            updates,
+            reference_lat,
+            tracks: FxHashMap::default(),
+            parked: 0,
+        }
+    }
+
+    /// Returns true if the given update's position should be written.
+    ///
+    /// When a parked object starts moving again, its position is filled in
+    /// with every coordinate that changed while it was parked
+    /// (orientation included), since we didn't write any of them.
+    pub fn forward(&mut self, up: &mut Update) -> bool {
+        let coords = coords::of(up);
+
+        let threshold = self.threshold;
+        let reference_lat = self.reference_lat;
+        let moved = |a: &Position, b: &Position| distance(a, b, reference_lat) > threshold;
+
+        let track = self.tracks.entry(up.id).or_insert(Track::Watching {
+            first: None,
+            current: Coords::default(),
+            updates: 0,
+        });
+
+        match track {
+            Track::Watching {
+                first,
+                current,
+                updates,
+            } => {
+                if let Some(c) = coords {
+                    coords::merge(current, c);
+                }
+                let here = Position::of(current);
+                if first.is_none() && here.is_complete() {
+                    *first = Some(here);
+                }
+                let start = match first {
+                    Some(f) => *f,
+                    // Can't tell if it's moving until we know where it is.
+                    None => return true,
+                };
+                if moved(&start, &here) {
+                    *track = Track::Moving;
+                    return true;
+                }
+
+                *updates += 1;
+                // It's had its `self.updates` updates; this is the first one we drop.
+                if *updates > self.updates {
+                    self.parked += 1;
+                    *track = Track::Parked {
+                        first: start,
+                        current: current.clone(),
+                    };
+                    false
+                } else {
+                    true
+                }
+            }
+            Track::Parked { first, current } => {
+                if let Some(c) = coords {
+                    coords::merge(current, c);
+                }
+                // Parked aircraft eventually taxi.
+                // Start forwarding again once they do.
+                if moved(first, &Position::of(current)) {
+                    let everything = Property::T(current.clone());
+                    *track = Track::Moving;
+                    if let Some(t) = up.props.iter_mut().find(|p| matches!(p, Property::T(_))) {
+                        *t = everything;
+                    }
+                    true
+                } else {
+                    false
+                }
+            }
+            Track::Moving => true,
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.tracks.remove(&id);
+    }
+}
+
+impl RecordTransform for ParkedFilter {
+    fn transform(&mut self, mut rec: Record, out: &mut Vec<Record>) {
+        match &mut rec {
+            Record::Update(up) => {
+                let keep = self.forward(up);
+                if !keep {
+                    up.props.retain(|p| !matches!(p, Property::T(_)));
+                    // Nothing left to update
+                    if up.props.is_empty() {
+                        return;
+                    }
+                }
+            }
+            Record::Remove(id) => self.remove(*id),
+            _ => {}
+        }
+        out.push(rec);
+    }
+
+    fn flush(&mut self, _out: &mut Vec<Record>) {
+        if self.parked > 0 {
+            warn!(
+                "Dropped positions of {} static objects under --drop-static",
+                self.parked
+            );
+        }
+    }
+}
+
+/// Holds back position changes smaller than `epsilon` meters,
+/// so objects jittering by centimeters don't get an update every frame.
+///
+/// Unlike [`ParkedFilter`], this never stops an object's updates for good:
+/// once it's been `max_hold` seconds since we last let its position through,
+/// we let the next one through even if it barely moved.
+/// When we do, it has every coordinate that changed since
+/// (orientation included), so nothing held back is lost for good.
+pub struct JitterFilter {
+    epsilon: f64,
+    max_hold: f64,
+    reference_lat: f64,
+    time: f64,
+    tracks: FxHashMap<u64, Held>,
+    /// How many positions we held back
+    pub held: u64,
+    /// About how many bytes of text those positions would have taken
+    pub bytes: u64,
+}
+
+struct Held {
+    /// Everything we know about the object's coordinates
+    current: Coords,
+    /// Where it was when we last let its position through, and when that was
+    emitted: Option<(Position, f64)>,
+}
+
+impl JitterFilter {
+    pub fn new(epsilon: f64, max_hold: f64, reference_lat: f64) -> Self {
+        Self {
+            epsilon,
+            max_hold,
+            reference_lat,
+            time: 0.0,
+            tracks: FxHashMap::default(),
+            held: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Hold back the update's coordinates if the object hasn't moved enough.
+    fn filter(&mut self, up: &mut Update) {
+        let i = match up.props.iter().position(|p| matches!(p, Property::T(_))) {
+            Some(i) => i,
+            None => return,
+        };
+        let coords = match &up.props[i] {
+            Property::T(c) => c,
+            _ => unreachable!(),
+        };
+
+        let held = self.tracks.entry(up.id).or_insert(Held {
+            current: Coords::default(),
+            emitted: None,
+        });
+        coords::merge(&mut held.current, coords);
+        let here = Position::of(&held.current);
+
+        if let Some((there, at)) = &held.emitted {
+            if here.is_complete()
+                && distance(there, &here, self.reference_lat) < self.epsilon
+                && self.time - at < self.max_hold
+            {
+                self.held += 1;
+                self.bytes += text_len(coords);
+                up.props.remove(i);
+                return;
+            }
+        }
+        held.emitted = Some((here, self.time));
+        up.props[i] = Property::T(held.current.clone());
+    }
+}
+
+impl RecordTransform for JitterFilter {
+    fn transform(&mut self, mut rec: Record, out: &mut Vec<Record>) {
+        match &mut rec {
+            Record::Frame(t) => self.time = *t,
+            Record::Update(up) => {
+                self.filter(up);
+                // Nothing left to update
+                if up.props.is_empty() {
+                    return;
+                }
+            }
+            Record::Remove(id) => {
+                self.tracks.remove(id);
+            }
+            _ => {}
+        }
+        out.push(rec);
+    }
+
+    fn flush(&mut self, _out: &mut Vec<Record>) {
+        info!(
+            "Held back {} position updates under --dedup-epsilon, saving about {}",
+            self.held,
+            ByteSize::b(self.bytes)
+        );
+    }
+}
+
+/// Roughly how long `T=lon|lat|alt...` is for these coordinates
+fn text_len(c: &Coords) -> u64 {
+    let fields = [
+        c.longitude,
+        c.latitude,
+        c.altitude,
+        c.u,
+        c.v,
+        c.roll,
+        c.pitch,
+        c.yaw,
+        c.heading,
+    ];
+    let last = fields.iter().rposition(Option::is_some).unwrap_or_default();
+    let values: usize = fields.iter().flatten().map(|v| v.to_string().len()).sum();
+    // "T=", a separator between each field, and the comma before it all
+    (2 + values + last + 1) as u64
+}
+
+/// A flat-earth approximation of the distance between two positions, in meters.
+/// Good enough for deciding whether something moved a meter or two.
+fn distance(a: &Position, b: &Position, reference_lat: f64) -> f64 {
+    let lat = a.lat.unwrap_or_default();
+    let dlat = (b.lat.unwrap_or_default() - lat) * METERS_PER_DEGREE;
+    let dlon = (b.lon.unwrap_or_default() - a.lon.unwrap_or_default())
+        * METERS_PER_DEGREE
+        * (reference_lat + lat).to_radians().cos();
+    let dalt = b.alt.unwrap_or_default() - a.alt.unwrap_or_default();
+    (dlat * dlat + dlon * dlon + dalt * dalt).sqrt()
+}
@@ -0,0 +1,107 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path;
+use log::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use tacview::record::{Property, Record};
+
+use crate::{filter::IdBase, transform::RecordTransform};
+
+/// Replaces objects' names (and optionally pilots) with ones from a mapping file.
+pub struct Rename {
+    names: FxHashMap<u64, NewName>,
+    /// Objects we've named since they were (last) added
+    named: FxHashSet<u64>,
+}
+
+struct NewName {
+    name: String,
+    pilot: Option<String>,
+}
+
+impl Rename {
+    /// Load a CSV of `id,name` or `id,name,pilot` lines.
+    ///
+    /// A first line of `id,...` is taken as a header and skipped.
+    /// Fields are trimmed, and can be wrapped in double quotes,
+    /// but names can't contain commas.
+    pub fn load(path: &Utf8Path, id_base: IdBase) -> Result<Self> {
+        let csv = fs::read_to_string(path).with_context(|| format!("Couldn't read {path}"))?;
+
+        let mut names = FxHashMap::default();
+        for (i, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split(',').map(|f| f.trim().trim_matches('"'));
+            let id = fields.next().unwrap_or_default();
+            if i == 0 && id.eq_ignore_ascii_case("id") {
+                continue;
+            }
+            let id = id_base
+                .parse(id)
+                .with_context(|| format!("{path} line {}", i + 1))?;
+            let name = match fields.next() {
+                Some(n) if !n.is_empty() => n.to_owned(),
+                _ => bail!("{path} line {}: no name for {}", i + 1, id_base.format(id)),
+            };
+            let pilot = fields.next().filter(|p| !p.is_empty()).map(str::to_owned);
+            if fields.next().is_some() {
+                bail!("{path} line {}: expected id,name[,pilot]", i + 1);
+            }
+            names.insert(id, NewName { name, pilot });
+        }
+        debug!("Loaded {} new names from {path}", names.len());
+
+        Ok(Self {
+            names,
+            named: FxHashSet::default(),
+        })
+    }
+}
+
+impl RecordTransform for Rename {
+    fn transform(&mut self, mut rec: Record, out: &mut Vec<Record>) {
+        match &mut rec {
+            Record::Update(up) => {
+                if let Some(new) = self.names.get(&up.id) {
+                    let mut has_name = false;
+                    let mut has_pilot = false;
+                    for prop in &mut up.props {
+                        match prop {
+                            Property::Name(n) => {
+                                new.name.clone_into(n);
+                                has_name = true;
+                            }
+                            Property::Pilot(p) => {
+                                if let Some(pilot) = &new.pilot {
+                                    pilot.clone_into(p);
+                                    has_pilot = true;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    // Name the object as soon as it appears,
+                    // even if it didn't have a name (or pilot) to replace.
+                    if self.named.insert(up.id) {
+                        if !has_name {
+                            up.props.push(Property::Name(new.name.clone()));
+                        }
+                        if let (Some(pilot), false) = (&new.pilot, has_pilot) {
+                            up.props.push(Property::Pilot(pilot.clone()));
+                        }
+                    }
+                }
+            }
+            Record::Remove(id) => {
+                self.named.remove(id);
+            }
+            _ => {}
+        }
+        out.push(rec);
+    }
+}
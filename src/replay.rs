@@ -0,0 +1,180 @@
+use std::{
+    fs::File,
+    io::{prelude::*, BufWriter},
+    num::NonZeroUsize,
+};
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use log::*;
+use rustc_hash::FxHashMap;
+use tacview::record::{Coords, GlobalProperty, Property, Record};
+
+use crate::{check_version, coords, filter::IdBase, input::Slice, Reader};
+
+/// Identifies (and versions) replay index files
+const MAGIC: &[u8; 8] = b"ACMIRPL1";
+
+/// One object's life, from its first update to its removal
+#[derive(Debug, Default)]
+struct Track {
+    id: u64,
+    spawn: f64,
+    despawn: f64,
+    kind: String,
+    /// Where it was as (time, longitude, latitude, altitude),
+    /// keeping only every `stride`th position
+    points: Vec<[f64; 4]>,
+    stride: usize,
+    /// Positions seen, including the ones we didn't keep
+    seen: usize,
+    /// The last position, which we always keep so the track ends in the right place
+    last: Option<[f64; 4]>,
+    /// Everything we know about where it is
+    coords: Coords,
+}
+
+impl Track {
+    fn new(id: u64, spawn: f64) -> Self {
+        Self {
+            id,
+            spawn,
+            despawn: spawn,
+            stride: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Note a position, thinning out the ones we've kept
+    /// so we never hold more than about twice `max` of them.
+    fn push(&mut self, point: [f64; 4], max: usize) {
+        if self.seen.is_multiple_of(self.stride) {
+            self.points.push(point);
+            if self.points.len() > max * 2 {
+                let mut i = 0;
+                self.points.retain(|_| {
+                    i += 1;
+                    i % 2 == 1
+                });
+                self.stride *= 2;
+            }
+        }
+        self.seen += 1;
+        self.last = Some(point);
+    }
+
+    /// Evenly spaced points along the track, at most `max` of them,
+    /// always including the first and last.
+    fn polyline(&self, max: usize) -> Vec<[f64; 4]> {
+        let mut points = self.points.clone();
+        if let Some(last) = self.last {
+            if points.last() != Some(&last) {
+                points.push(last);
+            }
+        }
+        if points.len() <= max {
+            return points;
+        }
+        if max == 1 {
+            return vec![points[0]];
+        }
+        (0..max)
+            .map(|i| points[i * (points.len() - 1) / (max - 1)])
+            .collect()
+    }
+}
+
+/// Write a viewer-friendly summary of every object in the ACMI to `out`:
+/// when it appeared and disappeared, its type, and a polyline of
+/// at most `polyline_points` positions along its track.
+///
+/// The file is the magic bytes `ACMIRPL1`, then a `u64` count of objects,
+/// then for each object:
+///
+/// - its ID as a `u64`,
+/// - its spawn and despawn times as `f64`s (the recording's last frame time
+///   for objects that are never removed),
+/// - its `Type` as a `u32` byte length then UTF-8 (empty if it never had one),
+/// - a `u32` count of points, then each point as four `f64`s:
+///   time, longitude, latitude, and altitude.
+///
+/// Everything is little-endian. Longitudes and latitudes are absolute,
+/// not relative to the ACMI's reference point. An ID that's removed
+/// and then reused gets an entry for each object that had it.
+pub fn export(
+    acmi: &Utf8Path,
+    out: &Utf8Path,
+    polyline_points: NonZeroUsize,
+    id_base: IdBase,
+    slice: Slice,
+    decimal_comma: bool,
+) -> Result<()> {
+    let max = polyline_points.get();
+    let mut fh = slice.open(acmi)?;
+    check_version(acmi, &mut fh, decimal_comma)?;
+
+    let mut reference = (0.0, 0.0);
+    let mut now = 0.0;
+    let mut live: FxHashMap<u64, Track> = FxHashMap::default();
+    let mut done: Vec<Track> = vec![];
+
+    for rec in Reader::new(&mut fh, decimal_comma, id_base)? {
+        match rec? {
+            Record::GlobalProperty(GlobalProperty::ReferenceLongitude(lon)) => reference.0 = lon,
+            Record::GlobalProperty(GlobalProperty::ReferenceLatitude(lat)) => reference.1 = lat,
+            Record::Frame(t) => now = t,
+            Record::Update(up) => {
+                let track = live.entry(up.id).or_insert_with(|| Track::new(up.id, now));
+                for prop in up.props {
+                    match prop {
+                        Property::Type(t) => track.kind = t,
+                        Property::T(c) => {
+                            coords::merge(&mut track.coords, &c);
+                            let at = &track.coords;
+                            if let (Some(lon), Some(lat)) = (at.longitude, at.latitude) {
+                                let alt = at.altitude.unwrap_or_default();
+                                let point = [now, lon + reference.0, lat + reference.1, alt];
+                                track.push(point, max);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Record::Remove(id) => {
+                if let Some(mut track) = live.remove(&id) {
+                    track.despawn = now;
+                    done.push(track);
+                }
+            }
+            Record::GlobalProperty(_) | Record::Event(_) => {}
+        }
+    }
+    for (_, mut track) in live {
+        track.despawn = now;
+        done.push(track);
+    }
+    done.sort_by(|a, b| a.spawn.total_cmp(&b.spawn).then(a.id.cmp(&b.id)));
+
+    let mut w =
+        BufWriter::new(File::create(out).with_context(|| format!("Couldn't create {out}"))?);
+    w.write_all(MAGIC)?;
+    w.write_all(&(done.len() as u64).to_le_bytes())?;
+    for track in &done {
+        w.write_all(&track.id.to_le_bytes())?;
+        w.write_all(&track.spawn.to_le_bytes())?;
+        w.write_all(&track.despawn.to_le_bytes())?;
+        w.write_all(&(track.kind.len() as u32).to_le_bytes())?;
+        w.write_all(track.kind.as_bytes())?;
+        let polyline = track.polyline(max);
+        w.write_all(&(polyline.len() as u32).to_le_bytes())?;
+        for point in polyline {
+            for v in point {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    w.flush().with_context(|| format!("Couldn't write {out}"))?;
+    info!("Wrote {} objects to {out}", done.len());
+    Ok(())
+}
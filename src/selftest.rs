@@ -0,0 +1,166 @@
+use std::{
+    collections::BTreeMap,
+    f64::consts::TAU,
+    fs::{self, File},
+    io::BufWriter,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use log::*;
+use tacview::record::{GlobalProperty, Record};
+
+use crate::{
+    compress,
+    coords::Tracker,
+    synth::{frame, header, object, position, remove},
+    temp_acmi, Args,
+};
+
+/// Where every object is at the end of each frame, in absolute lon/lat/alt
+type Snapshots = Vec<(f64, BTreeMap<u64, [f64; 3]>)>;
+
+/// Compress a synthetic recording and check that it still says the same thing:
+/// every object in the same place at the end of every frame.
+pub fn selftest() -> Result<()> {
+    let acmi = temp_acmi("selftest")?;
+
+    let records = synthesize();
+    let expected = snapshots(records.iter().cloned());
+    let mut w = tacview::Writer::new(BufWriter::new(
+        File::create(&acmi).with_context(|| format!("Couldn't create {acmi}"))?,
+    ))?;
+    for rec in records {
+        w.write(rec)?;
+    }
+    drop(w);
+
+    // Defaults, minus anything lossy the environment might set
+    let args = Args {
+        precision: None,
+        precision_spec: None,
+        dedup_epsilon: None,
+        max_objects: None,
+        time_scale: 1.0,
+        ..Args::parse_from(["acmi-compress", "selftest"])
+    };
+    let mut compressed = vec![];
+    let result = compress(&acmi, &mut compressed, None, &args);
+    if let Err(e) = fs::remove_file(&acmi) {
+        warn!("Couldn't remove {acmi}: {e}");
+    }
+    let stats = result.context("FAIL: couldn't compress the synthetic ACMI")?;
+
+    let parsed = tacview::Parser::new(compressed.as_slice())
+        .context("FAIL: couldn't parse the compressed header")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("FAIL: couldn't parse the compressed records")?;
+    let got = snapshots(parsed.into_iter());
+
+    if got.len() != expected.len() {
+        bail!(
+            "FAIL: compressed recording has {} frames, expected {}",
+            got.len(),
+            expected.len()
+        );
+    }
+    for ((t, want), (got_t, got)) in expected.iter().zip(&got) {
+        if t != got_t {
+            bail!("FAIL: frame at {got_t}s, expected {t}s");
+        }
+        if want.keys().ne(got.keys()) {
+            bail!("FAIL: different objects at {t}s");
+        }
+        for (id, w) in want {
+            let g = &got[id];
+            if w.iter().zip(g).any(|(a, b)| (a - b).abs() > 1e-7) {
+                bail!("FAIL: {id:x} at {t}s is at {g:?}, expected {w:?}");
+            }
+        }
+    }
+
+    println!(
+        "PASS: {} frames compressed from {} to {} bytes and back",
+        expected.len(),
+        stats.original_bytes,
+        stats.compressed_bytes
+    );
+    Ok(())
+}
+
+/// A few aircraft flying circles for a minute, one of which lands halfway through,
+/// and a tower that never moves.
+fn synthesize() -> Vec<Record> {
+    let mut records = header(-116.0, 36.0);
+    records.push(GlobalProperty::Title("acmi-compress selftest".to_owned()).into());
+    for t in 0..60 {
+        let t = t as f64;
+        records.push(frame(t));
+        if t == 0.0 {
+            records.push(object(0x100, "Tower", "Ground+Static"));
+            records.push(position(0x100, 0.5, 0.5, 0.0));
+        }
+        for (i, id) in [0x101u64, 0x102, 0x103].into_iter().enumerate() {
+            if id == 0x103 && t > 30.0 {
+                continue;
+            }
+            if t == 0.0 {
+                records.push(object(id, "F-16C", "Air+FixedWing"));
+            }
+            let angle = TAU * (t / 60.0 + i as f64 / 3.0);
+            let radius = 0.1 * (i + 1) as f64;
+            records.push(position(
+                id,
+                0.5 + radius * angle.cos(),
+                0.5 + radius * angle.sin(),
+                1000.0 * (i + 1) as f64,
+            ));
+        }
+        if t == 30.0 {
+            records.push(remove(0x103));
+        }
+    }
+    records
+}
+
+fn snapshots(records: impl Iterator<Item = Record>) -> Snapshots {
+    let mut reference = (0.0, 0.0);
+    let mut positions = Tracker::default();
+    let mut snapshots = vec![];
+    // Recordings start at #0 whether or not they say so
+    // (and the writer doesn't, since it's where it starts counting).
+    let mut frame = 0.0;
+
+    let snapshot = |positions: &Tracker, reference: (f64, f64)| {
+        positions
+            .iter()
+            .map(|(id, c)| {
+                let at = [
+                    c.longitude.unwrap_or_default() + reference.0,
+                    c.latitude.unwrap_or_default() + reference.1,
+                    c.altitude.unwrap_or_default(),
+                ];
+                (id, at)
+            })
+            .collect()
+    };
+
+    for rec in records {
+        match rec {
+            Record::GlobalProperty(GlobalProperty::ReferenceLongitude(lon)) => reference.0 = lon,
+            Record::GlobalProperty(GlobalProperty::ReferenceLatitude(lat)) => reference.1 = lat,
+            Record::Frame(t) if t == frame => {}
+            Record::Frame(t) => {
+                snapshots.push((frame, snapshot(&positions, reference)));
+                frame = t;
+            }
+            Record::Update(up) => {
+                positions.update(&up);
+            }
+            Record::Remove(id) => positions.remove(id),
+            Record::GlobalProperty(_) | Record::Event(_) => {}
+        }
+    }
+    snapshots.push((frame, snapshot(&positions, reference)));
+    snapshots
+}
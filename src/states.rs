@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, num::NonZeroUsize};
+
+use log::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::PropertyMap;
+
+/// The last properties we wrote for each object,
+/// so that we only have to write what changed.
+///
+/// Recordings full of short-lived objects (chaff, flares, bullets...)
+/// can make this grow without bound, so it can be capped,
+/// evicting the least recently updated objects.
+/// Forgetting an object is safe - its next update is just written in full.
+pub struct ObjectStates {
+    cap: Option<NonZeroUsize>,
+    /// Object ID -> (last time we touched it, its properties)
+    states: FxHashMap<u64, (u64, PropertyMap)>,
+    /// Touch time -> object ID, oldest first. Only maintained if we're capped.
+    recency: BTreeMap<u64, u64>,
+    /// Objects we evicted but haven't seen removed yet.
+    /// We still need to pass their removals through.
+    ///
+    /// Objects that are evicted and never removed would grow this forever,
+    /// so it's capped too. Once it overflows, we stop keeping track
+    /// and pass through every removal we don't recognize.
+    /// (Tacview ignores removals of objects it doesn't know.)
+    evicted: FxHashSet<u64>,
+    /// Have we given up on `evicted`?
+    overflowed: bool,
+    clock: u64,
+    pub evictions: u64,
+}
+
+impl ObjectStates {
+    pub fn new(cap: Option<NonZeroUsize>) -> Self {
+        Self {
+            cap,
+            states: FxHashMap::default(),
+            recency: BTreeMap::new(),
+            evicted: FxHashSet::default(),
+            overflowed: false,
+            clock: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Get the properties for the given object, marking it as recently used.
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut PropertyMap> {
+        let (touched, props) = self.states.get_mut(&id)?;
+        if self.cap.is_some() {
+            self.recency.remove(touched);
+            self.clock += 1;
+            *touched = self.clock;
+            self.recency.insert(self.clock, id);
+        }
+        Some(props)
+    }
+
+    /// Start tracking a new object, evicting the least recently used one
+    /// if that puts us over the cap.
+    pub fn insert(&mut self, id: u64, props: PropertyMap) {
+        self.clock += 1;
+        if let Some((old_touch, _)) = self.states.insert(id, (self.clock, props)) {
+            self.recency.remove(&old_touch);
+        }
+        self.evicted.remove(&id);
+
+        let cap = match self.cap {
+            Some(c) => c.get(),
+            None => return,
+        };
+        self.recency.insert(self.clock, id);
+
+        while self.states.len() > cap {
+            let (_, oldest) = self.recency.pop_first().expect("recency out of sync");
+            self.states.remove(&oldest);
+            self.evictions += 1;
+            if !self.overflowed {
+                self.evicted.insert(oldest);
+                if self.evicted.len() > cap {
+                    info!("Too many evicted objects to track; passing through unknown removals");
+                    self.evicted = FxHashSet::default();
+                    self.overflowed = true;
+                }
+            }
+        }
+    }
+
+    /// Stop tracking the given object,
+    /// returning true if we knew about it (even if we evicted it).
+    pub fn remove(&mut self, id: u64) -> bool {
+        if let Some((touched, _)) = self.states.remove(&id) {
+            self.recency.remove(&touched);
+            true
+        } else {
+            self.evicted.remove(&id) || self.overflowed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capped(cap: usize) -> ObjectStates {
+        ObjectStates::new(NonZeroUsize::new(cap))
+    }
+
+    #[test]
+    fn uncapped() {
+        let mut states = ObjectStates::new(None);
+        for id in 0..100 {
+            states.insert(id, PropertyMap::default());
+        }
+        assert!((0..100).all(|id| states.get_mut(id).is_some()));
+        assert_eq!(states.evictions, 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_updated() {
+        let mut states = capped(2);
+        states.insert(1, PropertyMap::default());
+        states.insert(2, PropertyMap::default());
+        // Touch 1, so 2 is the oldest.
+        assert!(states.get_mut(1).is_some());
+        states.insert(3, PropertyMap::default());
+
+        assert!(states.get_mut(1).is_some());
+        assert!(states.get_mut(2).is_none());
+        assert!(states.get_mut(3).is_some());
+        assert_eq!(states.evictions, 1);
+    }
+
+    #[test]
+    fn removes_evicted_objects() {
+        let mut states = capped(1);
+        states.insert(1, PropertyMap::default());
+        states.insert(2, PropertyMap::default());
+
+        // We forgot 1's state, but still need to pass its removal through - once.
+        assert!(states.remove(1));
+        assert!(!states.remove(1));
+        assert!(states.remove(2));
+        assert!(!states.remove(2));
+        assert!(!states.remove(3));
+    }
+
+    #[test]
+    fn reinserting_evicted_objects() {
+        let mut states = capped(1);
+        states.insert(1, PropertyMap::default());
+        states.insert(2, PropertyMap::default());
+        // 1 comes back (written in full), evicting 2.
+        states.insert(1, PropertyMap::default());
+
+        assert!(states.get_mut(1).is_some());
+        assert!(states.remove(1));
+        assert!(states.remove(2));
+        assert!(!states.remove(1));
+    }
+
+    #[test]
+    fn evicted_objects_are_capped() {
+        let mut states = capped(2);
+        for id in 0..10 {
+            states.insert(id, PropertyMap::default());
+        }
+        assert_eq!(states.evictions, 8);
+        assert!(states.evicted.len() <= 2);
+        // Having lost track, we can't tell which removals are redundant,
+        // so they all go through.
+        assert!(states.remove(0));
+        assert!(states.remove(100));
+    }
+}
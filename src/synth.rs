@@ -0,0 +1,47 @@
+//! Builders for synthetic recordings, for the selftest subcommand
+//! and the integration tests (which include this file with `#[path]`).
+
+use tacview::record::{Coords, GlobalProperty, Property, Record, Update};
+
+/// The header every synthetic ACMI starts with
+pub fn header(lon: f64, lat: f64) -> Vec<Record> {
+    vec![
+        GlobalProperty::ReferenceTime("2024-01-01T12:00:00Z".to_owned()).into(),
+        GlobalProperty::ReferenceLongitude(lon).into(),
+        GlobalProperty::ReferenceLatitude(lat).into(),
+    ]
+}
+
+pub fn frame(t: f64) -> Record {
+    Record::Frame(t)
+}
+
+/// Put the object at the given (reference-relative) position.
+pub fn position(id: u64, lon: f64, lat: f64, alt: f64) -> Record {
+    Update {
+        id,
+        props: vec![Property::T(Coords {
+            longitude: Some(lon),
+            latitude: Some(lat),
+            altitude: Some(alt),
+            ..Coords::default()
+        })],
+    }
+    .into()
+}
+
+/// Give the object a name and a type.
+pub fn object(id: u64, name: &str, kind: &str) -> Record {
+    Update {
+        id,
+        props: vec![
+            Property::Name(name.to_owned()),
+            Property::Type(kind.to_owned()),
+        ],
+    }
+    .into()
+}
+
+pub fn remove(id: u64) -> Record {
+    Record::Remove(id)
+}
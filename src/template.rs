@@ -0,0 +1,177 @@
+use anyhow::{bail, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+/// The extension we write. Plain ACMI, for now.
+const FORMAT: &str = "acmi";
+
+/// A parsed `--output-template`, like `compressed/{dir}/{stem}.{format}`
+#[derive(Debug)]
+pub struct OutputTemplate {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug)]
+enum Piece {
+    Literal(String),
+    /// The input's parent directory
+    Dir,
+    /// The input's file name, minus its extension
+    Stem,
+    /// The input's extension (`acmi` or `zip.acmi`)
+    Ext,
+    /// The extension of the output format
+    Format,
+}
+
+impl OutputTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(n) => name.push(n),
+                            None => bail!("Unclosed {{ in output template {template:?}"),
+                        }
+                    }
+                    let piece = match name.as_str() {
+                        "dir" => Piece::Dir,
+                        "stem" => Piece::Stem,
+                        "ext" => Piece::Ext,
+                        "format" => Piece::Format,
+                        unknown => bail!(
+                            "Unknown placeholder {{{unknown}}} in output template \
+                             (expected {{dir}}, {{stem}}, {{ext}}, or {{format}})"
+                        ),
+                    };
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(piece);
+                }
+                '}' => bail!("Unmatched }} in output template {template:?}"),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Self { pieces })
+    }
+
+    /// Fill in the template for the given input ACMI.
+    pub fn expand(&self, input: &Utf8Path) -> Utf8PathBuf {
+        let dir = match input.parent() {
+            Some(p) if !p.as_str().is_empty() => p.as_str(),
+            _ => ".",
+        };
+        let name = input.file_name().unwrap_or_default();
+        let (stem, ext) = split_extension(name);
+
+        let mut expanded = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(l) => expanded.push_str(l),
+                Piece::Dir => expanded.push_str(dir),
+                Piece::Stem => expanded.push_str(stem),
+                Piece::Ext => expanded.push_str(ext),
+                Piece::Format => expanded.push_str(FORMAT),
+            }
+        }
+        expanded.into()
+    }
+
+    /// Does the template give each input its own name?
+    /// (Without `{stem}`, every ACMI in a directory expands to the same path.)
+    pub fn is_per_input(&self) -> bool {
+        self.pieces.iter().any(|p| matches!(p, Piece::Stem))
+    }
+}
+
+/// Like [`Utf8Path::file_stem`], but treats `.zip.acmi` as a single extension.
+pub fn split_extension(name: &str) -> (&str, &str) {
+    for ext in ["zip.acmi", "acmi"] {
+        if let Some(stem) = name.strip_suffix(ext).and_then(|s| s.strip_suffix('.')) {
+            if !stem.is_empty() {
+                return (stem, ext);
+            }
+        }
+    }
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+        _ => (name, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str, input: &str) -> String {
+        OutputTemplate::parse(template)
+            .unwrap()
+            .expand(Utf8Path::new(input))
+            .into_string()
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            expand(
+                "compressed/{dir}/{stem}.{format}",
+                "missions/2024/sortie12.acmi"
+            ),
+            "compressed/missions/2024/sortie12.acmi"
+        );
+        assert_eq!(
+            expand("{dir}/{stem}.{ext}.bak", "missions/sortie12.zip.acmi"),
+            "missions/sortie12.zip.acmi.bak"
+        );
+        assert_eq!(expand("out/{stem}", "plain"), "out/plain");
+    }
+
+    #[test]
+    fn no_directory() {
+        assert_eq!(
+            expand("{dir}/{stem}.c.{format}", "foo.acmi"),
+            "./foo.c.acmi"
+        );
+    }
+
+    #[test]
+    fn bad_templates() {
+        for bad in ["{dir}/{name}.acmi", "{dir/foo", "foo}", "{}"] {
+            assert!(OutputTemplate::parse(bad).is_err(), "{bad:?} parsed");
+        }
+    }
+
+    #[test]
+    fn per_input() {
+        for (template, per_input) in [
+            ("{dir}/{stem}.c.{format}", true),
+            ("out/{stem}", true),
+            ("out/merged.{format}", false),
+            ("{dir}/compressed.{ext}", false),
+        ] {
+            let parsed = OutputTemplate::parse(template).unwrap();
+            assert_eq!(parsed.is_per_input(), per_input, "{template:?}");
+        }
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(split_extension("a.zip.acmi"), ("a", "zip.acmi"));
+        assert_eq!(split_extension("a.b.acmi"), ("a.b", "acmi"));
+        assert_eq!(split_extension("a.txt"), ("a", "txt"));
+        assert_eq!(split_extension("a"), ("a", ""));
+        // Not a stem and an extension, just a dotfile
+        assert_eq!(split_extension(".acmi"), (".acmi", ""));
+        assert_eq!(split_extension("zip.acmi"), ("zip", "acmi"));
+    }
+}
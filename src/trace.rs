@@ -0,0 +1,20 @@
+use std::{fs::File, io::BufWriter};
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use tracing_flame::{FlameLayer, FlushGuard};
+use tracing_subscriber::prelude::*;
+
+/// Record how long we spend in each tracing span (scan, read, parse, transform, write...)
+/// to `path` as folded stacks, which inferno-flamegraph and friends turn into flame graphs.
+///
+/// The file is only complete once the returned guard is dropped.
+///
+/// Without this, spans are just passed to the logger (at trace level, with -vvv).
+pub fn init(path: &Utf8Path) -> Result<FlushGuard<BufWriter<File>>> {
+    let (flame, guard) =
+        FlameLayer::with_file(path).with_context(|| format!("Couldn't create {path}"))?;
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(flame))
+        .context("Couldn't set up tracing")?;
+    Ok(guard)
+}
@@ -0,0 +1,455 @@
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path;
+use log::*;
+use rustc_hash::FxHashSet;
+use tacview::record::{Coords, Property, Record};
+
+use crate::{
+    filter::{Filter, IdBase},
+    offset_coords,
+    parked::{JitterFilter, ParkedFilter},
+    rename::Rename,
+    validate::Validator,
+    LL,
+};
+
+/// A stage between the reader and the writer that can drop, change,
+/// or split up the records passing through it.
+pub trait RecordTransform {
+    /// Push whatever should come of `rec` (if anything) onto `out`.
+    fn transform(&mut self, rec: Record, out: &mut Vec<Record>);
+
+    /// Push anything still held back onto `out` once the input runs out.
+    fn flush(&mut self, _out: &mut Vec<Record>) {}
+}
+
+pub type Transform = Box<dyn RecordTransform + Send>;
+
+/// Transforms applied in order, each to whatever came out of the last
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Transform>,
+    scratch: Vec<Record>,
+}
+
+impl Pipeline {
+    pub fn push(&mut self, stage: Transform) {
+        self.stages.push(stage);
+    }
+
+    pub fn extend(&mut self, stages: Vec<Transform>) {
+        self.stages.extend(stages);
+    }
+
+    /// Run the record through every stage, pushing what comes out the end onto `out`.
+    pub fn run(&mut self, rec: Record, out: &mut Vec<Record>) {
+        self.run_from(0, vec![rec], out);
+    }
+
+    /// Flush each stage in turn, running what it held back through the stages after it.
+    pub fn flush(&mut self, out: &mut Vec<Record>) {
+        for i in 0..self.stages.len() {
+            let mut flushed = vec![];
+            self.stages[i].flush(&mut flushed);
+            self.run_from(i + 1, flushed, out);
+        }
+    }
+
+    fn run_from(&mut self, first: usize, recs: Vec<Record>, out: &mut Vec<Record>) {
+        let start = out.len();
+        self.scratch.clear();
+        self.scratch.extend(recs);
+        for stage in &mut self.stages[first..] {
+            for r in self.scratch.drain(..) {
+                stage.transform(r, out);
+            }
+            self.scratch.extend(out.drain(start..));
+        }
+        out.append(&mut self.scratch);
+    }
+}
+
+/// What stages need to know about the recording they're transforming
+pub struct StageContext {
+    /// The reference point coordinates are relative to (after moving it)
+    pub reference: LL,
+    pub max_speed: f64,
+    /// How long dedup-epsilon can hold back a position (--dedup-max-hold)
+    pub dedup_max_hold: f64,
+    /// How many updates drop-static writes in full (--static-updates)
+    pub static_updates: u32,
+    pub id_base: IdBase,
+}
+
+/// A transform that can be named in a `--pipe`
+pub struct Stage {
+    pub name: &'static str,
+    /// The stage's argument (after `=`), if it takes one
+    pub arg: Option<&'static str>,
+    pub help: &'static str,
+    build: fn(Option<&str>, &StageContext) -> Result<Transform>,
+}
+
+pub const STAGES: &[Stage] = &[
+    Stage {
+        name: "only",
+        arg: Some("ID,..."),
+        help: "Only keep the objects with these IDs (in --id-base)",
+        build: |arg, ctx| {
+            let ids = arg
+                .unwrap_or_default()
+                .split(',')
+                .map(|id| ctx.id_base.parse(id.trim()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Box::new(Filter::default().only(&ids, None)))
+        },
+    },
+    Stage {
+        name: "event",
+        arg: Some("NAME,..."),
+        help: "Only keep events of these types (Destroyed, Message...)",
+        build: |arg, _| {
+            let kinds: Vec<String> = arg
+                .unwrap_or_default()
+                .split(',')
+                .map(|k| k.trim().to_owned())
+                .collect();
+            Ok(Box::new(Filter::default().only_events(&kinds)))
+        },
+    },
+    Stage {
+        name: "drop",
+        arg: Some("event|global"),
+        help: "Drop all events, or the global object (ID 0) after the header",
+        build: |arg, _| match arg {
+            Some(k) if k.eq_ignore_ascii_case("event") => {
+                Ok(Box::new(Filter::default().drop_events()))
+            }
+            Some(k) if k.eq_ignore_ascii_case("global") => {
+                Ok(Box::new(Filter::default().drop_global()))
+            }
+            _ => bail!("drop= takes event or global"),
+        },
+    },
+    Stage {
+        name: "rename",
+        arg: Some("CSV"),
+        help: "Rename objects from a CSV of id,name[,pilot] lines",
+        build: |arg, ctx| {
+            let csv = Utf8Path::new(arg.unwrap_or_default());
+            Ok(Box::new(Rename::load(csv, ctx.id_base)?))
+        },
+    },
+    Stage {
+        name: "repair",
+        arg: None,
+        help: "Drop physically impossible coordinates (uses --max-speed)",
+        build: |_, ctx| {
+            Ok(Box::new(Validator::new(
+                ctx.reference,
+                ctx.max_speed,
+                ctx.id_base,
+            )))
+        },
+    },
+    Stage {
+        name: "precision",
+        arg: Some("PLACES"),
+        help: "Round coordinates to this many decimal places (or per field, like LatLon=6,Alt=0)",
+        build: |arg, _| {
+            let arg = arg.unwrap_or_default();
+            match arg.parse::<u8>() {
+                Ok(places) => Ok(Box::new(Precision::uniform(places))),
+                Err(_) => Ok(Box::new(Precision::default().with_spec(arg)?)),
+            }
+        },
+    },
+    Stage {
+        name: "dedup-epsilon",
+        arg: Some("METERS"),
+        help: "Hold back positions that moved less than this (uses --dedup-max-hold)",
+        build: |arg, ctx| {
+            let epsilon = arg
+                .unwrap_or_default()
+                .parse::<f64>()
+                .context("dedup-epsilon= takes a distance in meters")?;
+            Ok(Box::new(JitterFilter::new(
+                epsilon,
+                ctx.dedup_max_hold,
+                ctx.reference.lat,
+            )))
+        },
+    },
+    Stage {
+        name: "drop-static",
+        arg: Some("METERS"),
+        help: "Drop positions of objects that stay within this of where they started (uses --static-updates)",
+        build: |arg, ctx| {
+            let threshold = arg
+                .unwrap_or_default()
+                .parse::<f64>()
+                .context("drop-static= takes a distance in meters")?;
+            Ok(Box::new(ParkedFilter::new(
+                threshold,
+                ctx.static_updates,
+                ctx.reference.lat,
+            )))
+        },
+    },
+    Stage {
+        name: "time-scale",
+        arg: Some("FACTOR"),
+        help: "Multiply every frame time by this factor",
+        build: |arg, _| {
+            let factor = arg
+                .unwrap_or_default()
+                .parse::<f64>()
+                .ok()
+                .filter(|f| f.is_finite() && *f > 0.0)
+                .context("time-scale= takes a positive number")?;
+            Ok(Box::new(TimeScale { factor }))
+        },
+    },
+    Stage {
+        name: "close-dangling",
+        arg: None,
+        help: "Remove every object still around at the end",
+        build: |_, _| Ok(Box::<CloseDangling>::default()),
+    },
+];
+
+/// Print each stage `--pipe` takes, with its argument and what it does.
+pub fn list() {
+    let usage = |s: &Stage| match s.arg {
+        Some(a) => format!("{}={a}", s.name),
+        None => s.name.to_owned(),
+    };
+    let width = STAGES
+        .iter()
+        .map(|s| usage(s).len())
+        .max()
+        .unwrap_or_default();
+    for s in STAGES {
+        println!("{:width$}  {}", usage(s), s.help);
+    }
+}
+
+/// Parse a pipeline like `only=1a,2b | precision=6 | repair` into its stages.
+pub fn parse_pipe(pipe: &str, ctx: &StageContext) -> Result<Vec<Transform>> {
+    let mut transforms = vec![];
+    for spec in pipe.split('|').map(str::trim) {
+        if spec.is_empty() {
+            bail!("Empty stage in --pipe {pipe:?}");
+        }
+        let (name, arg) = match spec.split_once('=') {
+            Some((n, a)) => (n.trim(), Some(a.trim())),
+            None => (spec, None),
+        };
+        let stage = match STAGES.iter().find(|s| s.name == name) {
+            Some(s) => s,
+            None => bail!(
+                "Unknown --pipe stage {name:?} (expected one of {})",
+                STAGES.iter().map(|s| s.name).collect::<Vec<_>>().join(", ")
+            ),
+        };
+        match (stage.arg, arg) {
+            (Some(a), None) => bail!("--pipe stage {name} needs an argument ({name}={a})"),
+            (None, Some(_)) => bail!("--pipe stage {name} doesn't take an argument"),
+            _ => {}
+        }
+        transforms
+            .push((stage.build)(arg, ctx).with_context(|| format!("Bad --pipe stage {spec:?}"))?);
+    }
+    Ok(transforms)
+}
+
+/// Moves coordinates from one reference point to another
+pub struct Reposition {
+    pub from: LL,
+    pub to: LL,
+}
+
+impl RecordTransform for Reposition {
+    fn transform(&mut self, mut rec: Record, out: &mut Vec<Record>) {
+        if let Record::Update(up) = &mut rec {
+            for prop in &mut up.props {
+                if let Property::T(c) = prop {
+                    offset_coords(c, &self.from, &self.to);
+                }
+            }
+        }
+        out.push(rec);
+    }
+}
+
+/// Multiplies every frame time by some factor
+pub struct TimeScale {
+    pub factor: f64,
+}
+
+impl RecordTransform for TimeScale {
+    fn transform(&mut self, rec: Record, out: &mut Vec<Record>) {
+        out.push(match rec {
+            Record::Frame(t) => Record::Frame(t * self.factor),
+            other => other,
+        });
+    }
+}
+
+/// Removes every object still around at the end of the recording,
+/// for consumers that expect each object to be removed after it's added.
+#[derive(Default)]
+pub struct CloseDangling {
+    live: FxHashSet<u64>,
+}
+
+impl RecordTransform for CloseDangling {
+    fn transform(&mut self, rec: Record, out: &mut Vec<Record>) {
+        match &rec {
+            Record::Update(up) => {
+                self.live.insert(up.id);
+            }
+            Record::Remove(id) => {
+                self.live.remove(id);
+            }
+            _ => {}
+        }
+        out.push(rec);
+    }
+
+    fn flush(&mut self, out: &mut Vec<Record>) {
+        let mut live: Vec<u64> = self.live.drain().collect();
+        live.sort_unstable();
+        info!("Closing {} objects that were never removed", live.len());
+        out.extend(live.into_iter().map(Record::Remove));
+    }
+}
+
+/// Rounds coordinates (positions and angles), and optionally other numeric
+/// properties, to some number of decimal places
+#[derive(Debug, Default)]
+pub struct Precision {
+    /// Places for each coordinate field, in the order `Coords` declares them
+    coords: [Option<u8>; 9],
+    /// Places for other numeric properties, by name
+    props: Vec<(&'static str, u8)>,
+}
+
+/// What `--precision-spec` calls each coordinate field (or pair of them)
+const COORD_NAMES: &[(&str, &[usize])] = &[
+    ("LatLon", &[0, 1]),
+    ("Lon", &[0]),
+    ("Lat", &[1]),
+    ("Alt", &[2]),
+    ("UV", &[3, 4]),
+    ("Roll", &[5]),
+    ("Pitch", &[6]),
+    ("Yaw", &[7]),
+    ("Heading", &[8]),
+];
+
+impl Precision {
+    /// Round every coordinate field to the same number of places.
+    pub fn uniform(places: u8) -> Self {
+        Self {
+            coords: [Some(places); 9],
+            props: vec![],
+        }
+    }
+
+    /// Override places per field with a spec like `LatLon=6,Alt=0,Heading=1`.
+    ///
+    /// Names are coordinate fields (see `COORD_NAMES`)
+    /// or numeric properties like `IAS` or `Throttle`.
+    /// Names we don't know are warned about and ignored.
+    pub fn with_spec(mut self, spec: &str) -> Result<Self> {
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, places) = match entry.split_once('=') {
+                Some((n, p)) => (n.trim(), p.trim()),
+                None => bail!("Precision spec {entry:?} should look like NAME=PLACES"),
+            };
+            let places = places
+                .parse::<u8>()
+                .with_context(|| format!("Precision spec {entry:?} needs a number of places"))?;
+
+            if let Some((_, fields)) = COORD_NAMES
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            {
+                for f in *fields {
+                    self.coords[*f] = Some(places);
+                }
+            } else if let Some(prop) = NUMERIC_PROPS.iter().find(|n| n.eq_ignore_ascii_case(name)) {
+                self.props.retain(|(n, _)| n != prop);
+                self.props.push((prop, places));
+            } else {
+                warn!("Ignoring unknown property {name:?} in precision spec");
+            }
+        }
+        Ok(self)
+    }
+}
+
+impl RecordTransform for Precision {
+    fn transform(&mut self, mut rec: Record, out: &mut Vec<Record>) {
+        if let Record::Update(up) = &mut rec {
+            for prop in &mut up.props {
+                if let Property::T(c) = prop {
+                    round_coords(c, &self.coords);
+                } else if let Some((name, value)) = numeric(prop) {
+                    if let Some((_, places)) = self.props.iter().find(|(n, _)| *n == name) {
+                        *value = round(*value, *places);
+                    }
+                }
+            }
+        }
+        out.push(rec);
+    }
+}
+
+/// Numeric properties a precision spec can round
+const NUMERIC_PROPS: &[&str] = &[
+    "IAS", "CAS", "TAS", "Mach", "AOA", "AOS", "AGL", "HDG", "HDM", "Throttle",
+];
+
+/// The property's name and value, if it's one of `NUMERIC_PROPS`
+fn numeric(prop: &mut Property) -> Option<(&'static str, &mut f64)> {
+    Some(match prop {
+        Property::IAS(v) => ("IAS", v),
+        Property::CAS(v) => ("CAS", v),
+        Property::TAS(v) => ("TAS", v),
+        Property::Mach(v) => ("Mach", v),
+        Property::AOA(v) => ("AOA", v),
+        Property::AOS(v) => ("AOS", v),
+        Property::AGL(v) => ("AGL", v),
+        Property::HDG(v) => ("HDG", v),
+        Property::HDM(v) => ("HDM", v),
+        Property::Throttle(v) => ("Throttle", v),
+        _ => return None,
+    })
+}
+
+fn round(v: f64, places: u8) -> f64 {
+    let scale = 10f64.powi(places.into());
+    (v * scale).round() / scale
+}
+
+/// Round each field of the coordinates to its number of decimal places (if it has one).
+fn round_coords(c: &mut Coords, places: &[Option<u8>; 9]) {
+    let fields = [
+        &mut c.longitude,
+        &mut c.latitude,
+        &mut c.altitude,
+        &mut c.u,
+        &mut c.v,
+        &mut c.roll,
+        &mut c.pitch,
+        &mut c.yaw,
+        &mut c.heading,
+    ];
+    for (field, places) in fields.into_iter().zip(places) {
+        if let (Some(v), Some(p)) = (field, places) {
+            *v = round(*v, *p);
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use std::{
+    fmt,
+    io::{self, prelude::*, BufWriter},
+};
+
+use anyhow::{bail, Result};
+use camino::Utf8Path;
+use log::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use tacview::record::{Coords, GlobalProperty, Property, Record, Update};
+
+use crate::{
+    check_version, coords, filter::IdBase, input::Slice, parse_original_ll,
+    transform::RecordTransform, Reader, LL,
+};
+
+/// Lowest altitude we believe, in meters (the Dead Sea is about -430)
+const MIN_ALTITUDE: f64 = -500.0;
+
+/// Highest altitude we believe, in meters (the Kármán line)
+const MAX_ALTITUDE: f64 = 100_000.0;
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// Something physically impossible about an object update
+#[derive(Debug, Copy, Clone)]
+pub enum Problem {
+    Latitude(f64),
+    Longitude(f64),
+    Altitude(f64),
+    /// The speed (m/s) the object would need to get from its last position to this one
+    Speed(f64),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Latitude(lat) => write!(f, "latitude {lat} is outside ±90"),
+            Self::Longitude(lon) => write!(f, "longitude {lon} is outside ±180"),
+            Self::Altitude(alt) => write!(
+                f,
+                "altitude {alt}m is outside {MIN_ALTITUDE}m to {MAX_ALTITUDE}m"
+            ),
+            Self::Speed(s) => write!(f, "teleported at {s:.0} m/s"),
+        }
+    }
+}
+
+/// Checks object updates for impossible positions and speeds.
+pub struct Validator {
+    reference: LL,
+    max_speed: f64,
+    id_base: IdBase,
+    time: f64,
+    tracks: FxHashMap<u64, Track>,
+    pub repaired: u64,
+}
+
+/// Everything we know about where an object is
+#[derive(Debug, Default, Clone)]
+struct Track {
+    coords: Coords,
+    /// The last time we had a complete position, and that position
+    last_fix: Option<(f64, Fix)>,
+    /// The last position we turned away for being too far from `last_fix`, and when
+    rejected: Option<(f64, Fix)>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Fix {
+    lat: f64,
+    lon: f64,
+    alt: f64,
+}
+
+impl Validator {
+    pub fn new(reference: LL, max_speed: f64, id_base: IdBase) -> Self {
+        Self {
+            reference,
+            max_speed,
+            id_base,
+            time: 0.0,
+            tracks: FxHashMap::default(),
+            repaired: 0,
+        }
+    }
+
+    /// The current frame time
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Check the given record, returning any problems with it.
+    ///
+    /// Positions out of bounds aren't remembered, so the next update is checked
+    /// against the last good one. Positions that moved too fast are checked against
+    /// both, so that one bad fix doesn't make every one after it look bad too.
+    pub fn check(&mut self, rec: &Record) -> Vec<Problem> {
+        match rec {
+            Record::Frame(t) => {
+                self.time = *t;
+                vec![]
+            }
+            Record::Remove(id) => {
+                self.tracks.remove(id);
+                vec![]
+            }
+            Record::Update(up) => self.check_update(up),
+            Record::GlobalProperty(_) | Record::Event(_) => vec![],
+        }
+    }
+
+    fn check_update(&mut self, up: &Update) -> Vec<Problem> {
+        let coords = match coords::of(up) {
+            Some(c) => c,
+            None => return vec![],
+        };
+
+        let track = self.tracks.entry(up.id).or_default();
+        let mut next = track.clone();
+        coords::merge(&mut next.coords, coords);
+        let lat = next.coords.latitude.map(|l| l + self.reference.lat);
+        let lon = next.coords.longitude.map(|l| l + self.reference.lon);
+        let alt = next.coords.altitude;
+
+        let mut problems = vec![];
+        if let Some(lat) = lat.filter(|l| !(-90.0..=90.0).contains(l)) {
+            problems.push(Problem::Latitude(lat));
+        }
+        if let Some(lon) = lon.filter(|l| !(-180.0..=180.0).contains(l)) {
+            problems.push(Problem::Longitude(lon));
+        }
+        if let Some(alt) = alt.filter(|a| !(MIN_ALTITUDE..=MAX_ALTITUDE).contains(a)) {
+            problems.push(Problem::Altitude(alt));
+        }
+        if !problems.is_empty() {
+            return problems;
+        }
+
+        if let (Some(lat), Some(lon)) = (lat, lon) {
+            let fix = Fix {
+                lat,
+                lon,
+                alt: alt.unwrap_or_default(),
+            };
+            let now = self.time;
+            let speed_since = |(then, prev): (f64, Fix)| {
+                (now > then).then(|| distance(&prev, &fix) / (now - then))
+            };
+            match next.last_fix.map(speed_since) {
+                None => next.last_fix = Some((now, fix)),
+                // Nothing to measure in the same frame
+                Some(None) => {}
+                Some(Some(speed)) if speed <= self.max_speed => {
+                    next.last_fix = Some((now, fix));
+                    next.rejected = None;
+                }
+                Some(Some(speed)) => {
+                    // Either this fix is bad, or the one we measured from was
+                    // (say, an exporter glitch on the object's first update).
+                    // If this one agrees with the last one we turned away,
+                    // the object really is over here, so measure from here on.
+                    let agrees = next
+                        .rejected
+                        .and_then(speed_since)
+                        .is_some_and(|s| s <= self.max_speed);
+                    if agrees {
+                        next.last_fix = Some((now, fix));
+                        next.rejected = None;
+                    } else {
+                        next.rejected = Some((now, fix));
+                        *track = next;
+                        return vec![Problem::Speed(speed)];
+                    }
+                }
+            }
+        }
+
+        *track = next;
+        problems
+    }
+
+    /// Check the given record, dropping any bad coordinates from it.
+    /// Returns false if there's nothing left of it worth writing.
+    pub fn repair(&mut self, rec: &mut Record) -> bool {
+        let problems = self.check(rec);
+        if problems.is_empty() {
+            return true;
+        }
+
+        let up = match rec {
+            Record::Update(up) => up,
+            _ => unreachable!("Only updates have problems"),
+        };
+        for p in &problems {
+            warn!(
+                "{} at {:.2}s: {p}; dropping its coordinates",
+                self.id_base.format(up.id),
+                self.time
+            );
+        }
+        self.repaired += 1;
+        up.props.retain(|p| !matches!(p, Property::T(_)));
+        !up.props.is_empty()
+    }
+}
+
+impl RecordTransform for Validator {
+    fn transform(&mut self, mut rec: Record, out: &mut Vec<Record>) {
+        if self.repair(&mut rec) {
+            out.push(rec);
+        }
+    }
+
+    fn flush(&mut self, _out: &mut Vec<Record>) {
+        if self.repaired > 0 {
+            warn!("Repaired {} object updates", self.repaired);
+        }
+    }
+}
+
+/// Great-circle distance between two fixes (plus the change in altitude), in meters
+fn distance(a: &Fix, b: &Fix) -> f64 {
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.lon - a.lon).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    let ground = 2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin();
+    let dalt = b.alt - a.alt;
+    (ground * ground + dalt * dalt).sqrt()
+}
+
+/// Check every object update in the ACMI, printing each problem found.
+pub fn validate(
+    acmi: &Utf8Path,
+    max_speed: f64,
+    id_base: IdBase,
+    slice: Slice,
+    decimal_comma: bool,
+) -> Result<()> {
+    let mut fh = slice.open(acmi)?;
+    check_version(acmi, &mut fh, decimal_comma)?;
+    let mut reader = Reader::new(&mut fh, decimal_comma, id_base)?;
+    let reference = parse_original_ll(&mut reader)?;
+
+    let mut validator = Validator::new(reference, max_speed, id_base);
+    let mut problems = 0u64;
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    // Objects that have been added but not (yet) removed
+    let mut live = FxHashSet::default();
+
+    info!("Checking all records");
+    for rec in reader {
+        let rec = rec?;
+        match &rec {
+            Record::Update(up) => {
+                live.insert(up.id);
+            }
+            Record::Remove(id) => {
+                live.remove(id);
+            }
+            _ => {}
+        }
+        for p in validator.check(&rec) {
+            let id = match &rec {
+                Record::Update(up) => up.id,
+                _ => unreachable!("Only updates have problems"),
+            };
+            writeln!(
+                out,
+                "{} at {:.2}s: {p}",
+                id_base.format(id),
+                validator.time()
+            )?;
+            problems += 1;
+        }
+    }
+
+    let mut dangling: Vec<u64> = live.into_iter().collect();
+    dangling.sort_unstable();
+    for id in &dangling {
+        writeln!(out, "{} is never removed", id_base.format(*id))?;
+    }
+    out.flush()?;
+    if !dangling.is_empty() {
+        info!(
+            "{} objects are never removed (see --close-dangling)",
+            dangling.len()
+        );
+    }
+
+    if problems > 0 {
+        bail!("Found {problems} problems in {acmi}");
+    }
+    info!("No problems found in {acmi}");
+    Ok(())
+}
+
+/// Check just the ACMI's header: its FileType and FileVersion,
+/// that it has a well-formed ReferenceTime, and that its reference point
+/// (if it has one) is on the globe. Stops at the first record after the header.
+pub fn validate_header(
+    acmi: &Utf8Path,
+    id_base: IdBase,
+    slice: Slice,
+    decimal_comma: bool,
+) -> Result<()> {
+    let mut fh = slice.open(acmi)?;
+    check_version(acmi, &mut fh, decimal_comma)?;
+
+    let mut problems = vec![];
+    let mut reference_time = None;
+    for rec in Reader::new(&mut fh, decimal_comma, id_base)? {
+        match rec? {
+            Record::GlobalProperty(GlobalProperty::ReferenceTime(t)) => reference_time = Some(t),
+            Record::GlobalProperty(GlobalProperty::ReferenceLatitude(lat)) => {
+                if !(-90.0..=90.0).contains(&lat) {
+                    problems.push(format!("ReferenceLatitude {lat} is outside ±90"));
+                }
+            }
+            Record::GlobalProperty(GlobalProperty::ReferenceLongitude(lon)) => {
+                if !(-180.0..=180.0).contains(&lon) {
+                    problems.push(format!("ReferenceLongitude {lon} is outside ±180"));
+                }
+            }
+            Record::GlobalProperty(_) => {}
+            _ => break,
+        }
+    }
+    match reference_time {
+        None => problems.push("no ReferenceTime".to_owned()),
+        Some(t) if !is_iso8601(&t) => problems.push(format!(
+            "ReferenceTime {t:?} isn't an ISO 8601 UTC time (like 2024-01-01T12:00:00Z)"
+        )),
+        Some(_) => {}
+    }
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for p in &problems {
+        writeln!(out, "{p}")?;
+    }
+    out.flush()?;
+
+    if !problems.is_empty() {
+        bail!("Found {} problems in {acmi}'s header", problems.len());
+    }
+    info!("No problems found in {acmi}'s header");
+    Ok(())
+}
+
+/// Does this look like `YYYY-MM-DDTHH:MM:SS[.fff]Z`?
+fn is_iso8601(t: &str) -> bool {
+    let (datetime, rest) = match t.strip_suffix('Z') {
+        Some(dt) if dt.len() >= 19 && dt.is_char_boundary(19) => dt.split_at(19),
+        _ => return false,
+    };
+    let shape_ok = datetime.bytes().enumerate().all(|(i, b)| match i {
+        4 | 7 => b == b'-',
+        10 => b == b'T',
+        13 | 16 => b == b':',
+        _ => b.is_ascii_digit(),
+    });
+    let fraction_ok = match rest.strip_prefix('.') {
+        Some(f) => !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()),
+        None => rest.is_empty(),
+    };
+    shape_ok && fraction_ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{frame, position};
+
+    /// A second of flight at about 160 m/s, in degrees of longitude at 36N
+    const STEP: f64 = 0.0018;
+
+    fn validator() -> Validator {
+        Validator::new(
+            LL {
+                lat: 36.0,
+                lon: -116.0,
+            },
+            3000.0,
+            IdBase::Hex,
+        )
+    }
+
+    /// Feed the validator an object flying east, one position a second,
+    /// with the given positions replaced by glitches.
+    /// Returns the times that had problems.
+    fn flagged(glitches: &[u32]) -> Vec<u32> {
+        let mut v = validator();
+        let mut bad = vec![];
+        for t in 0..10 {
+            v.check(&frame(t as f64));
+            let rec = if glitches.contains(&t) {
+                position(1, 10.0, 10.0, 1000.0)
+            } else {
+                position(1, 0.5 + t as f64 * STEP, 0.5, 1000.0)
+            };
+            if !v.check(&rec).is_empty() {
+                bad.push(t);
+            }
+        }
+        bad
+    }
+
+    #[test]
+    fn good_track() {
+        assert!(flagged(&[]).is_empty());
+    }
+
+    #[test]
+    fn glitch_mid_track() {
+        assert_eq!(flagged(&[4]), [4]);
+    }
+
+    #[test]
+    fn glitch_on_first_fix() {
+        // We can't tell the first fix was bad until the second disagrees with it
+        // and the third agrees with the second.
+        // Everything after that is fine.
+        assert_eq!(flagged(&[0]), [1]);
+    }
+
+    #[test]
+    fn repeated_glitches() {
+        // A position teleporting back and forth never agrees with itself.
+        assert_eq!(flagged(&[3, 5, 7]), [3, 5, 7]);
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut v = validator();
+        v.check(&frame(0.0));
+        let problems = v.check(&position(1, 0.0, 60.0, 200_000.0));
+        assert!(matches!(
+            problems.as_slice(),
+            [Problem::Latitude(_), Problem::Altitude(_)]
+        ));
+    }
+
+    #[test]
+    fn repair_drops_bad_coordinates() {
+        let mut v = validator();
+        let mut kept = 0;
+        for t in 0..10 {
+            let mut frame_rec = frame(t as f64);
+            assert!(v.repair(&mut frame_rec));
+            let lon = if t == 0 { 10.0 } else { 0.5 + t as f64 * STEP };
+            let mut rec = position(1, lon, 0.5, 1000.0);
+            if v.repair(&mut rec) {
+                kept += 1;
+            }
+        }
+        // Only the position that disagreed with the bad first fix is dropped.
+        assert_eq!(kept, 9);
+        assert_eq!(v.repaired, 1);
+    }
+}
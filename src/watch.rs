@@ -0,0 +1,113 @@
+use std::{
+    fs, thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use log::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{compress_to, template::OutputTemplate, Args};
+
+/// How we last saw a file that's waiting to be compressed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Seen {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+pub struct Watch<'a> {
+    pub dir: &'a Utf8Path,
+    pub template: OutputTemplate,
+    pub interval: Duration,
+    pub remove_source: bool,
+}
+
+impl Watch<'_> {
+    /// Poll the directory forever, compressing each new ACMI
+    /// once it's stopped changing for a full `interval`.
+    ///
+    /// ACMIs already there when we start are left alone,
+    /// as are the ones we write. ACMIs we fail to compress are tried again
+    /// if they change, in case the sim was just taking a break from writing them.
+    pub fn run(self, args: &Args) -> Result<()> {
+        let mut done: FxHashSet<Utf8PathBuf> = self.scan()?.into_keys().collect();
+        let mut failed: FxHashMap<Utf8PathBuf, Seen> = FxHashMap::default();
+        let mut pending: FxHashMap<Utf8PathBuf, Seen> = FxHashMap::default();
+        info!(
+            "Watching {} for new ACMIs ({} already there)",
+            self.dir,
+            done.len()
+        );
+
+        loop {
+            thread::sleep(self.interval);
+
+            for (acmi, seen) in self.scan()? {
+                if done.contains(&acmi) || failed.get(&acmi) == Some(&seen) {
+                    continue;
+                }
+                // If it changed since we last looked, the sim is probably still writing it.
+                if pending.insert(acmi.clone(), seen) != Some(seen) {
+                    debug!("{acmi} is new or still changing");
+                    continue;
+                }
+                pending.remove(&acmi);
+                failed.remove(&acmi);
+
+                // Don't pick up what we write as a new ACMI, even if we fail halfway.
+                let output = self.template.expand(&acmi);
+                done.insert(output.clone());
+
+                match self.compress(&acmi, &output, args) {
+                    Ok(()) => {
+                        done.insert(acmi);
+                    }
+                    Err(e) => {
+                        error!("{e:#}");
+                        info!("Will try {acmi} again if it changes");
+                        failed.insert(acmi, seen);
+                    }
+                }
+            }
+        }
+    }
+
+    fn compress(&self, acmi: &Utf8Path, output: &Utf8Path, args: &Args) -> Result<()> {
+        compress_to(acmi, output, None, args)?;
+
+        if self.remove_source {
+            fs::remove_file(acmi).with_context(|| format!("Couldn't remove {acmi}"))?;
+            info!("Removed {acmi}");
+        }
+        Ok(())
+    }
+
+    /// Every ACMI in the directory, and how it looks right now
+    fn scan(&self) -> Result<FxHashMap<Utf8PathBuf, Seen>> {
+        let mut found = FxHashMap::default();
+        let entries =
+            fs::read_dir(self.dir).with_context(|| format!("Couldn't list {}", self.dir))?;
+        for entry in entries {
+            let path = match Utf8PathBuf::try_from(entry?.path()) {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+            if !path.as_str().ends_with(".acmi") {
+                continue;
+            }
+            let md = match path.metadata() {
+                Ok(md) if md.is_file() => md,
+                // Gone already, or a directory named like an ACMI
+                _ => continue,
+            };
+            let seen = Seen {
+                len: md.len(),
+                modified: md.modified().ok(),
+            };
+            found.insert(path, seen);
+        }
+        Ok(found)
+    }
+}
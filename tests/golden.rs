@@ -0,0 +1,166 @@
+//! Compress the fixtures in tests/fixtures and check what comes out.
+
+use std::{env, fs, process::Command};
+
+use tacview::record::Record;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn compress(acmi: &str) -> Vec<u8> {
+    compress_with(acmi, &[])
+}
+
+fn compress_with(acmi: &str, flags: &[&str]) -> Vec<u8> {
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .args(flags)
+        .arg(acmi)
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(
+        out.status.success(),
+        "acmi-compress failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    out.stdout
+}
+
+fn parse(acmi: &[u8]) -> Vec<Record> {
+    tacview::Parser::new(acmi)
+        .expect("Couldn't parse compressed header")
+        .collect::<Result<_, _>>()
+        .expect("Couldn't parse compressed records")
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Counts {
+    globals: usize,
+    frames: usize,
+    updates: usize,
+    events: usize,
+    removes: usize,
+}
+
+fn count(records: &[Record]) -> Counts {
+    let mut c = Counts::default();
+    for rec in records {
+        match rec {
+            Record::GlobalProperty(_) => c.globals += 1,
+            Record::Frame(_) => c.frames += 1,
+            Record::Update(_) => c.updates += 1,
+            Record::Event(_) => c.events += 1,
+            Record::Remove(_) => c.removes += 1,
+        }
+    }
+    c
+}
+
+/// What sample.acmi should compress down to:
+/// the repeated frame, the tower's unchanged updates,
+/// and the redundant removal should all be gone.
+const SAMPLE_COUNTS: Counts = Counts {
+    globals: 4,
+    frames: 4,
+    updates: 5,
+    events: 1,
+    removes: 2,
+};
+
+/// Check the output against the fixture's expected output, byte for byte.
+///
+/// When the output is meant to change, rerun with `BLESS=1`
+/// to write the new expected output, and commit it.
+fn check_expected(compressed: &[u8], expected: &str) {
+    let path = fixture(expected);
+    if env::var_os("BLESS").is_some() {
+        fs::write(&path, compressed).expect("Couldn't write expected output");
+        return;
+    }
+    let expected = fs::read(&path)
+        .unwrap_or_else(|e| panic!("Couldn't read {path} ({e}); run with BLESS=1 to create it"));
+    assert!(
+        compressed == expected,
+        "Output differs from {path} (rerun with BLESS=1 if that's intended)\n\
+         --- expected\n{}\n--- got\n{}",
+        String::from_utf8_lossy(&expected),
+        String::from_utf8_lossy(compressed)
+    );
+}
+
+#[test]
+fn plain() {
+    let original = fs::read(fixture("sample.acmi")).unwrap();
+    let compressed = compress(&fixture("sample.acmi"));
+
+    check_expected(&compressed, "sample.expected.acmi");
+    assert_eq!(count(&parse(&compressed)), SAMPLE_COUNTS);
+    assert!(
+        compressed.len() < original.len(),
+        "Compressed {} bytes to {}",
+        original.len(),
+        compressed.len()
+    );
+}
+
+#[test]
+fn zipped() {
+    let compressed = compress(&fixture("sample.zip.acmi"));
+    assert_eq!(count(&parse(&compressed)), SAMPLE_COUNTS);
+
+    // Same recording, so we should get the same bytes out.
+    assert_eq!(compressed, compress(&fixture("sample.acmi")));
+}
+
+#[test]
+fn zip64() {
+    // Stores its sizes in a zip64 extra field, like entries over 4GB do.
+    let compressed = compress(&fixture("sample.zip64.zip.acmi"));
+    assert_eq!(compressed, compress(&fixture("sample.acmi")));
+}
+
+#[test]
+fn empty() {
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .arg(fixture("empty.acmi"))
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("is empty"));
+}
+
+#[test]
+fn max_objects() {
+    // sample.acmi removes the tower twice, which isn't a third object.
+    let compressed = compress_with(&fixture("sample.acmi"), &["--max-objects", "2"]);
+    assert_eq!(compressed, compress(&fixture("sample.acmi")));
+}
+
+#[test]
+fn max_objects_lenient() {
+    // The tower doesn't fit, so its updates and both its removals are dropped.
+    let compressed = compress_with(
+        &fixture("sample.acmi"),
+        &["--max-objects", "1", "--lenient"],
+    );
+    let records = parse(&compressed);
+    assert!(records.iter().all(|r| match r {
+        Record::Update(up) => up.id == 0xa01,
+        Record::Remove(id) => *id == 0xa01,
+        _ => true,
+    }));
+    assert_eq!(count(&records).removes, 1);
+}
+
+#[test]
+fn empty_slice() {
+    // A slice that starts at the end of the file has nothing in it.
+    let len = fs::metadata(fixture("sample.acmi")).unwrap().len();
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .args(["--input-offset", &len.to_string()])
+        .arg(fixture("sample.acmi"))
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("is empty"));
+}
@@ -0,0 +1,601 @@
+//! Build small ACMIs record by record, compress them, and check what comes out.
+//!
+//! Handier than a fixture when a test needs a specific sequence of records.
+
+use std::{fs::File, io::BufWriter, path::PathBuf, process::Command};
+
+use tacview::record::{Coords, Event, EventKind, GlobalProperty, Property, Record, Update};
+
+#[path = "../src/synth.rs"]
+mod synth;
+
+use synth::{frame, header, object, position, remove};
+
+/// Write the records to `<test name>.acmi` in Cargo's scratch directory.
+fn write(name: &str, records: Vec<Record>) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.acmi"));
+    let fh = BufWriter::new(File::create(&path).expect("Couldn't create ACMI"));
+    let mut w = tacview::Writer::new(fh).expect("Couldn't write header");
+    for rec in records {
+        w.write(rec).expect("Couldn't write record");
+    }
+    path
+}
+
+/// Compress the records with the given flags and parse what comes out.
+fn compress(name: &str, records: Vec<Record>, flags: &[&str]) -> Vec<Record> {
+    run(flags, &[write(name, records)])
+}
+
+/// Run acmi-compress with the given flags and inputs, and parse what comes out.
+fn run(flags: &[&str], acmis: &[PathBuf]) -> Vec<Record> {
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .args(flags)
+        .args(acmis)
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(
+        out.status.success(),
+        "acmi-compress failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    tacview::Parser::new(out.stdout.as_slice())
+        .expect("Couldn't parse compressed header")
+        .collect::<Result<_, _>>()
+        .expect("Couldn't parse compressed records")
+}
+
+/// The IDs of every update, in order
+fn updated(records: &[Record]) -> Vec<u64> {
+    records
+        .iter()
+        .filter_map(|r| match r {
+            Record::Update(up) => Some(up.id),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn unchanged_updates_are_dropped() {
+    let mut records = header(-116.0, 36.0);
+    for t in 0..4 {
+        records.push(frame(t as f64));
+        records.push(position(1, 0.5, 0.5, 1000.0));
+    }
+    records.push(remove(1));
+
+    let out = compress("unchanged_updates_are_dropped", records, &[]);
+    assert_eq!(updated(&out), [1]);
+}
+
+#[test]
+fn only_keeps_the_given_objects() {
+    let mut records = header(-116.0, 36.0);
+    records.push(frame(0.0));
+    records.push(object(1, "Viper", "Air+FixedWing"));
+    records.push(object(2, "Hornet", "Air+FixedWing"));
+    for t in 1..3 {
+        records.push(frame(t as f64));
+        records.push(position(1, 0.5, t as f64 / 10.0, 1000.0));
+        records.push(position(2, 0.6, t as f64 / 10.0, 2000.0));
+    }
+
+    let out = compress("only_keeps_the_given_objects", records, &["--only", "2"]);
+    assert!(!updated(&out).is_empty());
+    assert!(updated(&out).iter().all(|id| *id == 2));
+}
+
+#[test]
+fn precision_rounds_coordinates() {
+    let mut records = header(-116.0, 36.0);
+    records.push(frame(0.0));
+    records.push(position(1, 0.123456789, 0.987654321, 1000.123456));
+
+    let out = compress(
+        "precision_rounds_coordinates",
+        records,
+        &["--precision", "2"],
+    );
+    let coords: Vec<f64> = out
+        .iter()
+        .filter_map(|r| match r {
+            Record::Update(up) => Some(&up.props),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|p| match p {
+            Property::T(c) => Some([c.longitude, c.latitude, c.altitude]),
+            _ => None,
+        })
+        .flatten()
+        .flatten()
+        .collect();
+    assert!(!coords.is_empty());
+    for c in coords {
+        let hundredths = c * 100.0;
+        assert!(
+            (hundredths - hundredths.round()).abs() < 1e-6,
+            "{c} has more than two decimal places"
+        );
+    }
+}
+
+#[test]
+fn merge_joins_files_end_to_end() {
+    // Objects 1 and 2 fly for a few seconds...
+    let mut first = header(-116.0, 36.0);
+    for t in 0..3 {
+        first.push(frame(t as f64));
+        first.push(position(1, 0.5, 0.5 + t as f64 / 100.0, 1000.0));
+        first.push(position(2, 0.6, 0.6, 2000.0));
+    }
+    // ...then the next recording starts at t = 10 without object 2...
+    let mut second = header(-116.0, 36.0);
+    for t in 10..12 {
+        second.push(frame(t as f64));
+        second.push(position(1, 0.5, 0.6 + t as f64 / 100.0, 1000.0));
+        second.push(position(3, 0.7, 0.7, 3000.0));
+    }
+    second.push(GlobalProperty::Comments("Second sortie".to_owned()).into());
+    // ...and the last is recorded around a different reference point.
+    let mut third = header(-115.0, 36.0);
+    for t in [0.0, 5.0] {
+        third.push(frame(t));
+        third.push(position(1, 0.5, 0.8 + t / 100.0, 1000.0));
+        third.push(position(3, 0.7, 0.7, 3000.0));
+    }
+
+    let files = [
+        write("merge_first", first),
+        write("merge_second", second),
+        write("merge_third", third),
+    ];
+    let out = run(&["merge"], &files);
+
+    // Each file picks up where the last left off, without repeating its last frame.
+    let frames: Vec<f64> = out
+        .iter()
+        .filter_map(|r| match r {
+            Record::Frame(t) => Some(*t),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(frames, [0.0, 1.0, 2.0, 3.0, 8.0]);
+
+    // Object 2 didn't make it into the second file, but 1 and 3 carry on.
+    let removed: Vec<u64> = out
+        .iter()
+        .filter_map(|r| match r {
+            Record::Remove(id) => Some(*id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(removed, [2]);
+
+    // Only the first header is kept, but later global properties aren't dropped.
+    let headers = out
+        .iter()
+        .filter(|r| matches!(r, Record::GlobalProperty(GlobalProperty::ReferenceTime(_))))
+        .count();
+    assert_eq!(headers, 1);
+    assert!(out.contains(&GlobalProperty::Comments("Second sortie".to_owned()).into()));
+
+    // The third file's positions are moved to the first's reference point.
+    let last_lon = out.iter().rev().find_map(|r| match r {
+        Record::Update(up) if up.id == 1 => up.props.iter().find_map(|p| match p {
+            Property::T(c) => c.longitude,
+            _ => None,
+        }),
+        _ => None,
+    });
+    assert_eq!(last_lon, Some(1.5));
+}
+
+#[test]
+fn precision_spec_rounds_fields_separately() {
+    let mut records = header(-116.0, 36.0);
+    records.push(frame(0.0));
+    records.push(position(1, 0.123456789, 0.987654321, 1000.123456));
+
+    let out = compress(
+        "precision_spec_rounds_fields_separately",
+        records,
+        &["--precision", "3", "--precision-spec", "Alt=0"],
+    );
+    let coords = out.iter().find_map(|r| match r {
+        Record::Update(up) => up.props.iter().find_map(|p| match p {
+            Property::T(c) => Some(c.clone()),
+            _ => None,
+        }),
+        _ => None,
+    });
+    let coords = coords.expect("No coordinates in output");
+    assert_eq!(coords.altitude, Some(1000.0));
+    assert_eq!(coords.latitude, Some(0.988));
+}
+
+/// Two objects, with the global object's events and properties between their updates
+fn with_globals() -> Vec<Record> {
+    let mut records = header(-116.0, 36.0);
+    // Start at #1: the writer skips #0 as a repeat of where it starts counting.
+    for t in 1..=3 {
+        records.push(frame(t as f64));
+        records.push(position(1, 0.5, 0.5 + t as f64 / 100.0, 1000.0));
+        records.push(message(&format!("Frame {t}")));
+        records.push(position(2, 0.6, 0.6 + t as f64 / 100.0, 2000.0));
+        records.push(GlobalProperty::Comments(format!("Comment {t}")).into());
+    }
+    records
+}
+
+fn message(text: &str) -> Record {
+    Event {
+        kind: EventKind::Message,
+        params: vec![],
+        text: Some(text.to_owned()),
+    }
+    .into()
+}
+
+/// How many events and global properties after the first frame
+fn globals(records: &[Record]) -> (usize, usize) {
+    let first_frame = records
+        .iter()
+        .position(|r| matches!(r, Record::Frame(_)))
+        .unwrap_or(records.len());
+    let after = &records[first_frame..];
+    let events = after
+        .iter()
+        .filter(|r| matches!(r, Record::Event(_)))
+        .count();
+    let props = after
+        .iter()
+        .filter(|r| matches!(r, Record::GlobalProperty(_)))
+        .count();
+    (events, props)
+}
+
+#[test]
+fn only_keeps_the_global_object() {
+    let out = compress(
+        "only_keeps_the_global_object",
+        with_globals(),
+        &["--only", "1"],
+    );
+    assert!(updated(&out).iter().all(|id| *id == 1));
+    assert_eq!(globals(&out), (3, 3));
+}
+
+#[test]
+fn drop_global_keeps_the_header() {
+    let out = compress(
+        "drop_global_keeps_the_header",
+        with_globals(),
+        &["--drop-global"],
+    );
+    assert_eq!(globals(&out), (0, 0));
+    assert!(out.iter().any(|r| matches!(
+        r,
+        Record::GlobalProperty(GlobalProperty::ReferenceLongitude(_))
+    )));
+    assert!(updated(&out).contains(&1) && updated(&out).contains(&2));
+}
+
+#[test]
+fn keep_global_drops_every_object() {
+    let out = compress(
+        "keep_global_drops_every_object",
+        with_globals(),
+        &["--keep-global"],
+    );
+    assert!(updated(&out).is_empty());
+    assert_eq!(globals(&out), (3, 3));
+}
+
+#[test]
+fn dedup_epsilon_holds_back_jitter() {
+    let mut records = header(-116.0, 36.0);
+    for t in 0..6 {
+        records.push(frame(t as f64));
+        // About a centimeter each frame
+        records.push(position(1, 0.5, 0.5 + t as f64 * 1e-7, 1000.0));
+    }
+
+    let out = compress(
+        "dedup_epsilon_holds_back_jitter",
+        records,
+        &["--dedup-epsilon", "1", "--dedup-max-hold", "2"],
+    );
+    // The first position, then one every two seconds
+    assert_eq!(updated(&out), [1, 1, 1]);
+}
+
+#[test]
+fn dedup_epsilon_pipe_stage() {
+    let mut records = header(-116.0, 36.0);
+    for t in 0..6 {
+        records.push(frame(t as f64));
+        records.push(position(1, 0.5, 0.5 + t as f64 * 1e-7, 1000.0));
+    }
+
+    let out = compress(
+        "dedup_epsilon_pipe_stage",
+        records,
+        &["--dedup-max-hold", "2", "--pipe", "dedup-epsilon=1"],
+    );
+    assert_eq!(updated(&out), [1, 1, 1]);
+}
+
+#[test]
+fn output_template_wont_overwrite_the_input() {
+    let mut records = header(-116.0, 36.0);
+    records.push(frame(0.0));
+    records.push(position(1, 0.5, 0.5, 1000.0));
+    let acmi = write("output_template_wont_overwrite_the_input", records);
+    let before = std::fs::read(&acmi).unwrap();
+
+    // Run from the ACMI's directory, so `{dir}` expands to `.`
+    // and the output is `./<name>.acmi` - the input, spelled differently.
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .current_dir(acmi.parent().unwrap())
+        .args(["-o", "{dir}/{stem}.{format}"])
+        .arg(acmi.file_name().unwrap())
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("with itself"));
+    assert_eq!(std::fs::read(&acmi).unwrap(), before);
+}
+
+#[test]
+fn output_template_needs_a_stem_for_batches() {
+    let mut records = header(-116.0, 36.0);
+    records.push(frame(1.0));
+    records.push(position(1, 0.5, 0.5, 1000.0));
+    let first = write(
+        "output_template_needs_a_stem_for_batches_1",
+        records.clone(),
+    );
+    let second = write("output_template_needs_a_stem_for_batches_2", records);
+    let merged = first.with_file_name("output_template_needs_a_stem_for_batches.out.acmi");
+    let _ = std::fs::remove_file(&merged);
+
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .arg("-o")
+        .arg(&merged)
+        .args([&first, &second])
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("{stem}"));
+    assert!(!merged.exists(), "Compressed into {merged:?} anyway");
+}
+
+#[test]
+fn heatmap_rejects_huge_grids() {
+    let mut records = header(-116.0, 36.0);
+    records.push(frame(0.0));
+    records.push(position(1, 0.5, 0.5, 1000.0));
+    let acmi = write("heatmap_rejects_huge_grids", records);
+
+    // Squaring this overflows, where it used to panic.
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .args(["to-heatmap", "--cells", &u64::MAX.to_string()])
+        .arg(&acmi)
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("is too many"));
+}
+
+/// The percentage --dedup-report prints for the records, with the given flags
+fn dedup_report(name: &str, records: Vec<Record>, flags: &[&str]) -> f64 {
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .arg("--dedup-report")
+        .args(flags)
+        .arg(write(name, records))
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(
+        out.status.success(),
+        "acmi-compress failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let report = String::from_utf8(out.stdout).unwrap();
+    let percent = report
+        .split("would remove ")
+        .nth(1)
+        .and_then(|r| r.split('%').next())
+        .unwrap_or_else(|| panic!("No percentage in {report:?}"));
+    percent.parse().unwrap()
+}
+
+#[test]
+fn dedup_report_counts_lossy_options() {
+    let records = || {
+        let mut records = header(-116.0, 36.0);
+        for t in 0..6 {
+            records.push(frame(t as f64));
+            // Jitters by about a centimeter every frame
+            records.push(position(1, 0.5, 0.5 + (t % 2) as f64 * 1e-7, 1000.0));
+        }
+        records
+    };
+
+    let lossless = dedup_report("dedup_report_lossless", records(), &[]);
+    let lossy = dedup_report("dedup_report_lossy", records(), &["--dedup-epsilon", "1"]);
+    assert!(
+        lossy > lossless,
+        "--dedup-epsilon only got {lossy}%, versus {lossless}% without it"
+    );
+}
+
+#[test]
+fn drop_static_keeps_other_properties() {
+    let mut records = header(-116.0, 36.0);
+    for t in 0..10 {
+        records.push(frame(t as f64));
+        let mut parked = position(1, 0.5, 0.5, 0.0);
+        // Long after it's parked, it takes a hit.
+        if t == 8 {
+            if let Record::Update(up) = &mut parked {
+                up.props.push(Property::Health(0.5));
+            }
+        }
+        records.push(parked);
+    }
+
+    let out = compress(
+        "drop_static_keeps_other_properties",
+        records,
+        &["--drop-static", "--static-updates", "2"],
+    );
+    let health = out.iter().any(|r| match r {
+        Record::Update(up) => up.props.contains(&Property::Health(0.5)),
+        _ => false,
+    });
+    assert!(health, "Parked object's Health change was dropped");
+}
+
+#[test]
+fn drop_static_pipe_stage() {
+    let mut records = header(-116.0, 36.0);
+    for t in 0..6 {
+        records.push(frame(t as f64));
+        records.push(position(1, 0.5, 0.5 + t as f64 * 1e-7, 0.0));
+    }
+
+    let out = compress(
+        "drop_static_pipe_stage",
+        records,
+        &["--static-updates", "2", "--pipe", "drop-static=1"],
+    );
+    assert_eq!(updated(&out), [1, 1]);
+}
+
+#[test]
+fn time_scale_pipe_stage() {
+    let mut records = header(-116.0, 36.0);
+    for t in 1..4 {
+        records.push(frame(t as f64));
+        records.push(position(1, 0.5, 0.5 + t as f64 * 0.01, 1000.0));
+    }
+
+    let out = compress(
+        "time_scale_pipe_stage",
+        records,
+        &["--pipe", "time-scale=0.5"],
+    );
+    let frames: Vec<f64> = out
+        .iter()
+        .filter_map(|r| match r {
+            Record::Frame(t) => Some(*t),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(frames, [0.5, 1.0, 1.5]);
+}
+
+#[test]
+fn drop_static_resumes_with_what_changed() {
+    let mut records = header(-116.0, 36.0);
+    for t in 0..10 {
+        records.push(frame(t as f64));
+        // Parked, but turning in place, then taxiing away.
+        let lon = if t < 8 {
+            0.5
+        } else {
+            0.5 + (t - 7) as f64 / 1000.0
+        };
+        let coords = if t < 8 {
+            Coords {
+                yaw: Some(t as f64 * 10.0),
+                ..coords(lon)
+            }
+        } else {
+            coords(lon)
+        };
+        records.push(
+            Update {
+                id: 1,
+                props: vec![Property::T(coords)],
+            }
+            .into(),
+        );
+    }
+
+    let out = compress(
+        "drop_static_resumes_with_what_changed",
+        records,
+        &["--drop-static", "--static-updates", "2"],
+    );
+    // The first position written after it moves has the heading it turned to.
+    let yaws: Vec<_> = out
+        .iter()
+        .filter_map(|r| match r {
+            Record::Update(up) => up.props.iter().find_map(|p| match p {
+                Property::T(c) => Some(c.yaw),
+                _ => None,
+            }),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(yaws.iter().flatten().last(), Some(&70.0));
+}
+
+/// Coordinates at the given (reference-relative) longitude
+fn coords(lon: f64) -> Coords {
+    Coords {
+        longitude: Some(lon),
+        latitude: Some(0.5),
+        altitude: Some(0.0),
+        ..Coords::default()
+    }
+}
+
+#[test]
+fn archive_members_are_compressed() {
+    let mut records = header(-116.0, 36.0);
+    for t in 0..3 {
+        records.push(frame(t as f64));
+        records.push(position(1, 0.5, 0.5 + t as f64 / 100.0, 1000.0));
+    }
+    let acmi = write("archive_member", records);
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("archive_members_are_compressed");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let tar = dir.join("sorties.tar.gz");
+    let gz =
+        flate2::write::GzEncoder::new(File::create(&tar).unwrap(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(gz);
+    builder
+        .append_path_with_name(&acmi, "2024/sortie1.acmi")
+        .unwrap();
+    builder.append_path_with_name(&acmi, "notes.txt").unwrap();
+    builder.into_inner().unwrap().finish().unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_acmi-compress"))
+        .arg("--archive")
+        .arg(&tar)
+        .arg("-o")
+        .arg(format!("{}/out/{{dir}}/{{stem}}.{{format}}", dir.display()))
+        .output()
+        .expect("Couldn't run acmi-compress");
+    assert!(
+        out.status.success(),
+        "acmi-compress failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8_lossy(&out.stdout).contains("2024/sortie1.acmi"));
+
+    // Members keep their directories in the archive, and non-ACMIs are skipped.
+    let compressed = std::fs::read(dir.join("out/2024/sortie1.acmi")).unwrap();
+    let parsed: Vec<Record> = tacview::Parser::new(compressed.as_slice())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(updated(&parsed), [1, 1, 1]);
+    assert!(!dir.join("out/notes.acmi").exists());
+}